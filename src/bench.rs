@@ -0,0 +1,72 @@
+use crate::config::{Config, EventKey, Rule};
+use crate::remapper::{Event, EventType, Remapper};
+use evdev_rs::enums::EV_KEY;
+use std::time::{Duration, Instant};
+
+// Events are remapped until at least this many have been measured
+const EVENTS: usize = 100_000;
+
+// Measures how long Remapper takes for each event, by typing every rule of `then` of a device
+pub(crate) fn run(config: Result<Config, String>, device: usize) {
+  let config: &'static Config = Box::leak(Box::new(config.unwrap_or_else(|error| {
+    eprintln!("{}", error);
+    std::process::exit(1);
+  })));
+  let device = config.devices.get(device).unwrap_or_else(|| {
+    eprintln!("The config has only {} devices", config.devices.len());
+    std::process::exit(1);
+  });
+
+  let events = events_for(&device.then);
+  let mut remapper = Remapper::new(device, false);
+  let mut durations = Vec::with_capacity(EVENTS + events.len());
+  while durations.len() < EVENTS {
+    for event in events.iter() {
+      let start = Instant::now();
+      remapper.remap(event.clone());
+      durations.push(start.elapsed());
+    }
+  }
+
+  durations.sort_unstable();
+  let total: Duration = durations.iter().sum();
+  let percentile = |percent: usize| durations[(durations.len() - 1) * percent / 100];
+  println!("rules:  {}", device.then.len());
+  println!("events: {}", durations.len());
+  println!("mean:   {:?}", total / durations.len() as u32);
+  println!("p50:    {:?}", percentile(50));
+  println!("p99:    {:?}", percentile(99));
+  println!("max:    {:?}", percentile(100));
+}
+
+// Presses `from.with` and `from.key` of each rule, and releases them in reverse
+fn events_for(rules: &[Rule]) -> Vec<Event> {
+  let mut result = Vec::new();
+  let event = |event_type: EventType, key: &EventKey| Event {
+    event_type,
+    key: key.clone(),
+  };
+
+  for rule in rules.iter() {
+    let mut keys: Vec<EventKey> = rule
+      .from
+      .with
+      .iter()
+      .flatten()
+      .map(|modifier| (&modifier.sides()[0]).into())
+      .collect();
+    keys.push(rule.from.key.clone());
+
+    result.extend(keys.iter().map(|key| event(EventType::Press, key)));
+    result.extend(keys.iter().rev().map(|key| event(EventType::Release, key)));
+  }
+
+  // Without rules, it's still worth knowing the overhead
+  if result.is_empty() {
+    let key: EventKey = EV_KEY::KEY_A.into();
+    result.push(event(EventType::Press, &key));
+    result.push(event(EventType::Release, &key));
+  }
+
+  result
+}
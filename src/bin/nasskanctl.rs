@@ -0,0 +1,50 @@
+use serde_json::json;
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::net::UnixStream;
+
+// Same as control::SOCKET_PATH in nasskan
+const SOCKET_PATH: &str = "/run/nasskan/control.sock";
+
+const USAGE: &str = "Usage: nasskanctl <list|state|stats|pause|resume|reload|profile [NAME]|explain [on|off]>";
+
+fn main() {
+  let args: Vec<String> = std::env::args().skip(1).collect();
+  let request = match args.iter().map(String::as_str).collect::<Vec<&str>>().as_slice() {
+    [command @ "list"] | [command @ "state"] | [command @ "stats"] | [command @ "pause"]
+    | [command @ "resume"] | [command @ "reload"] => json!({ "command": command }),
+    ["profile"] => json!({ "command": "profile", "profile": null }),
+    ["profile", profile] => json!({ "command": "profile", "profile": profile }),
+    ["explain"] => json!({ "command": "explain" }),
+    ["explain", "on"] => json!({ "command": "explain", "enabled": true }),
+    ["explain", "off"] => json!({ "command": "explain", "enabled": false }),
+    _ => {
+      eprintln!("{}", USAGE);
+      std::process::exit(2);
+    }
+  };
+
+  let stream = UnixStream::connect(SOCKET_PATH).unwrap_or_else(|error| {
+    eprintln!("Connecting to {} failed: {}", SOCKET_PATH, error);
+    std::process::exit(1);
+  });
+  let response = exchange(stream, &request.to_string()).unwrap_or_else(|error| {
+    eprintln!("{}", error);
+    std::process::exit(1);
+  });
+
+  println!("{}", serde_json::to_string_pretty(&response).unwrap());
+  if response["ok"] != true {
+    std::process::exit(1);
+  }
+}
+
+// nasskan closes the connection if something goes wrong, which leaves a short or empty line
+fn exchange(mut stream: UnixStream, request: &str) -> Result<serde_json::Value, String> {
+  writeln!(stream, "{}", request).map_err(|error| format!("Sending the request failed: {}", error))?;
+
+  let mut line = String::new();
+  BufReader::new(stream)
+    .read_line(&mut line)
+    .map_err(|error| format!("Reading the response failed: {}", error))?;
+  serde_json::from_str(&line).map_err(|error| format!("nasskan sent an invalid response: {}", error))
+}
@@ -0,0 +1,491 @@
+// `include`, named modifier groups and named rule sets.
+// They're expanded while deserializing, so the rest of nasskan never sees them.
+use super::{keys, v2, Config, Modifier, Rule};
+use schemars::gen::SchemaGenerator;
+use schemars::schema::Schema;
+use serde::de::value::MapAccessDeserializer;
+use serde::de::DeserializeOwned;
+use serde::de::{Error, IntoDeserializer, MapAccess, SeqAccess, Visitor};
+use serde::{Deserialize, Deserializer};
+use std::cell::RefCell;
+use std::collections::{BTreeMap, BTreeSet};
+use std::convert::TryFrom;
+use std::path::{Path, PathBuf};
+
+thread_local! {
+  // Names defined by the config which is being loaded
+  static NAMED: RefCell<Named> = RefCell::new(Named::default());
+}
+
+#[derive(Default)]
+struct Named {
+  modifiers: BTreeMap<String, BTreeSet<Modifier>>,
+  rules: BTreeMap<String, Vec<Rule>>,
+}
+
+// Parsed before anything else, because rules may refer to these
+#[derive(Deserialize)]
+struct Header {
+  #[serde(default)]
+  include: Vec<PathBuf>,
+  #[serde(default)]
+  modifiers: BTreeMap<String, Vec<String>>,
+}
+
+#[derive(Deserialize)]
+struct RuleSets {
+  #[serde(default)]
+  rules: BTreeMap<String, Vec<Rule>>,
+}
+
+#[derive(Deserialize)]
+struct Version {
+  version: u8,
+}
+
+// Included files and overlays can't have `version` or other global settings.
+// Their devices have the same version as the main file.
+#[derive(Deserialize)]
+struct Included<T> {
+  #[serde(default = "Vec::new")]
+  devices: Vec<T>,
+}
+
+struct Source {
+  path: PathBuf,
+  // For finding files included twice
+  canonical: PathBuf,
+  content: String,
+  header: Header,
+}
+
+pub(super) fn load(path: &Path, overlay: Option<&Path>) -> Result<Config, String> {
+  let mut sources = Vec::new();
+  read_with_includes(path, &mut sources, &mut Vec::new())?;
+  let overlay = overlay.map(read_overlay).transpose()?;
+
+  let result = expand(&sources, overlay.as_ref());
+  NAMED.with(|named| *named.borrow_mut() = Named::default());
+  result
+}
+
+fn expand(sources: &[Source], overlay: Option<&Source>) -> Result<Config, String> {
+  let mut groups = BTreeMap::new();
+  for source in sources.iter().chain(overlay) {
+    for (name, members) in source.header.modifiers.iter() {
+      if groups.insert(name.as_str(), (source, members)).is_some() {
+        return Err(invalid(source, format!("modifiers {} is defined twice", name)));
+      }
+    }
+  }
+  let mut modifiers = BTreeMap::new();
+  for name in groups.keys() {
+    resolve_group(name, &groups, &mut modifiers, &mut Vec::new())?;
+  }
+  NAMED.with(|named| named.borrow_mut().modifiers = modifiers);
+
+  let mut rules = BTreeMap::new();
+  for source in sources.iter().chain(overlay) {
+    let rule_sets: RuleSets = source.parse()?;
+    for (name, rule_set) in rule_sets.rules {
+      if rules.contains_key(&name) {
+        return Err(invalid(source, format!("rules {} is defined twice", name)));
+      }
+      rules.insert(name, rule_set);
+    }
+  }
+  NAMED.with(|named| named.borrow_mut().rules = rules);
+
+  let main = &sources[0];
+  let version: Version = main.parse()?;
+  let (mut config, overlaid): (Config, usize) = match version.version {
+    1 => parse_version(sources, overlay, |config: &mut Config| &mut config.devices)?,
+    2 => {
+      let (config, overlaid) =
+        parse_version(sources, overlay, |config: &mut v2::Config| &mut config.devices)?;
+      (config.into(), overlaid)
+    }
+    version => return Err(format!("version {} is not supported", version)),
+  };
+
+  // The overlay's devices replace the system's ones with the same `if`
+  let system = config.devices.split_off(overlaid);
+  let overlaid: BTreeSet<_> = config.devices.iter().map(|device| device.if_.clone()).collect();
+  config
+    .devices
+    .extend(system.into_iter().filter(|device| !overlaid.contains(&device.if_)));
+  Ok(config)
+}
+
+// Also returns how many devices at the beginning came from the overlay
+fn parse_version<C: DeserializeOwned, D: DeserializeOwned>(
+  sources: &[Source],
+  overlay: Option<&Source>,
+  devices: impl Fn(&mut C) -> &mut Vec<D>,
+) -> Result<(C, usize), String> {
+  let (main, included) = sources.split_first().unwrap();
+  let mut config: C = main.parse()?;
+  for source in included.iter() {
+    let included: Included<D> = source.parse()?;
+    devices(&mut config).extend(included.devices);
+  }
+
+  let overlay: Vec<D> = match overlay {
+    Some(source) => source.parse::<Included<D>>()?.devices,
+    None => Vec::new(),
+  };
+  let overlaid = overlay.len();
+  devices(&mut config).splice(0..0, overlay);
+  Ok((config, overlaid))
+}
+
+type Groups<'a> = BTreeMap<&'a str, (&'a Source, &'a Vec<String>)>;
+
+// Groups may have other groups as members, so they're resolved before groups using them
+fn resolve_group(
+  name: &str,
+  groups: &Groups,
+  resolved: &mut BTreeMap<String, BTreeSet<Modifier>>,
+  using: &mut Vec<String>,
+) -> Result<BTreeSet<Modifier>, String> {
+  if let Some(group) = resolved.get(name) {
+    return Ok(group.clone());
+  }
+  let (source, members) = groups[name];
+  if using.iter().any(|user| user == name) {
+    return Err(invalid(source, format!("modifiers {} contains itself", name)));
+  }
+
+  using.push(name.to_string());
+  let mut group = BTreeSet::new();
+  for member in members.iter() {
+    if groups.contains_key(member.as_str()) {
+      group.extend(resolve_group(member, groups, resolved, using)?);
+    } else {
+      group.extend(resolve_modifier(member).map_err(|error| invalid(source, error))?);
+    }
+  }
+  using.pop();
+
+  resolved.insert(name.to_string(), group.clone());
+  Ok(group)
+}
+
+// The first one is `path` itself. Paths are relative to the file including them.
+// A file included by several files is read once, but a file including itself is an error.
+fn read_with_includes(
+  path: &Path,
+  sources: &mut Vec<Source>,
+  including: &mut Vec<PathBuf>,
+) -> Result<(), String> {
+  let canonical = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+  if including.contains(&canonical) {
+    return Err(format!("{} includes itself", path.display()));
+  }
+  if sources.iter().any(|source| source.canonical == canonical) {
+    return Ok(());
+  }
+
+  let content = std::fs::read_to_string(path)
+    .map_err(|error| format!("{} could not be opened: {}", path.display(), error))?;
+  let header: Header = parse(path, &content)?;
+  let includes: Vec<PathBuf> = header
+    .include
+    .iter()
+    .map(|include| path.parent().unwrap_or_else(|| Path::new("/")).join(include))
+    .collect();
+
+  sources.push(Source {
+    path: path.to_path_buf(),
+    canonical: canonical.clone(),
+    content,
+    header,
+  });
+  including.push(canonical);
+  for include in includes.iter() {
+    read_with_includes(include, sources, including)?;
+  }
+  including.pop();
+  Ok(())
+}
+
+// A user's config. It's read by root, so it can't include other files
+fn read_overlay(path: &Path) -> Result<Source, String> {
+  let content = std::fs::read_to_string(path)
+    .map_err(|error| format!("{} could not be opened: {}", path.display(), error))?;
+  let header: Header = parse(path, &content)?;
+  if !header.include.is_empty() {
+    return Err(format!("{} can't have include", path.display()));
+  }
+
+  Ok(Source {
+    path: path.to_path_buf(),
+    canonical: path.to_path_buf(),
+    content,
+    header,
+  })
+}
+
+impl Source {
+  fn parse<T: DeserializeOwned>(&self) -> Result<T, String> {
+    parse(&self.path, &self.content)
+  }
+}
+
+// The format is chosen by the extension. YAML unless it's .toml or .json
+pub(super) fn parse<T: DeserializeOwned>(path: &Path, content: &str) -> Result<T, String> {
+  let result = match path.extension().and_then(|extension| extension.to_str()) {
+    Some("toml") => toml::from_str(content).map_err(|error| error.to_string()),
+    Some("json") => serde_json::from_str(content).map_err(|error| error.to_string()),
+    _ => serde_yaml::from_str(content).map_err(|error| error.to_string()),
+  };
+  result.map_err(|error| format!("{} has invalid shape: {}", path.display(), error))
+}
+
+fn invalid(source: &Source, error: impl std::fmt::Display) -> String {
+  format!("{} has invalid shape: {}", source.path.display(), error)
+}
+
+fn resolve_modifier(name: &str) -> Result<BTreeSet<Modifier>, String> {
+  let builtin = match name {
+    "ANY_MODIFIER" => Some(vec![
+      Modifier::LEFTSHIFT,
+      Modifier::RIGHTSHIFT,
+      Modifier::LEFTCTRL,
+      Modifier::RIGHTCTRL,
+      Modifier::LEFTALT,
+      Modifier::RIGHTALT,
+      Modifier::LEFTMETA,
+      Modifier::RIGHTMETA,
+    ]),
+    _ => None,
+  };
+  if let Some(builtin) = builtin {
+    return Ok(builtin.into_iter().collect());
+  }
+
+  if let Some(group) = NAMED.with(|named| named.borrow().modifiers.get(name).cloned()) {
+    return Ok(group);
+  }
+
+  let upper = name.to_uppercase();
+  let deserializer: serde::de::value::StrDeserializer<serde::de::value::Error> =
+    upper.as_str().into_deserializer();
+  if let Ok(modifier) = Modifier::deserialize(deserializer) {
+    return Ok(vec![modifier].into_iter().collect());
+  }
+
+  // Keys like KEY_LEFTCTRL are still sided modifiers
+  let key = keys::parse(name)
+    .map_err(|error| format!("{}, and not a modifier or modifier group either", error))?;
+  let modifier = Modifier::try_from(&key).unwrap_or(Modifier::Key(key));
+  Ok(vec![modifier].into_iter().collect())
+}
+
+// For `with` and `without`. Groups are replaced with all of their members
+pub(super) fn modifiers<'a, D: Deserializer<'a>>(
+  deserializer: D,
+) -> Result<Option<BTreeSet<Modifier>>, D::Error> {
+  let names: Option<Vec<String>> = Deserialize::deserialize(deserializer)?;
+  let names = match names {
+    Some(names) => names,
+    None => return Ok(None),
+  };
+
+  let mut result = BTreeSet::new();
+  for name in names.iter() {
+    result.extend(resolve_modifier(name).map_err(D::Error::custom)?);
+  }
+  Ok(Some(result))
+}
+
+// For `then`. Names of rule sets are replaced with their rules
+pub(super) fn rules<'a, D: Deserializer<'a>>(deserializer: D) -> Result<Vec<Rule>, D::Error> {
+  RuleList::deserialize(deserializer).map(|rules| rules.0)
+}
+
+// For `profiles`
+pub(super) fn profiles<'a, D: Deserializer<'a>>(
+  deserializer: D,
+) -> Result<BTreeMap<String, Vec<Rule>>, D::Error> {
+  let profiles: BTreeMap<String, RuleList> = Deserialize::deserialize(deserializer)?;
+  Ok(
+    profiles
+      .into_iter()
+      .map(|(name, rules)| (name, rules.0))
+      .collect(),
+  )
+}
+
+// Rules, or names of rule sets
+pub(super) fn rules_schema(generator: &mut SchemaGenerator) -> Schema {
+  let rule = generator.subschema_for::<Rule>();
+  serde_json::from_value(serde_json::json!({
+    "type": "array",
+    "items": { "anyOf": [rule, { "type": "string" }] },
+  }))
+  .unwrap()
+}
+
+struct RuleList(Vec<Rule>);
+
+impl<'a> Deserialize<'a> for RuleList {
+  fn deserialize<D: Deserializer<'a>>(deserializer: D) -> Result<Self, D::Error> {
+    deserializer.deserialize_seq(RuleListVisitor)
+  }
+}
+
+struct RuleListVisitor;
+impl<'a> Visitor<'a> for RuleListVisitor {
+  type Value = RuleList;
+
+  fn expecting(&self, formatter: &mut std::fmt::Formatter) -> Result<(), std::fmt::Error> {
+    formatter.write_str("a list of rules or names of rule sets")
+  }
+
+  fn visit_seq<A: SeqAccess<'a>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+    let mut result = Vec::new();
+    while let Some(entry) = seq.next_element::<RuleEntry>()? {
+      result.extend(entry.0);
+    }
+    Ok(RuleList(result))
+  }
+}
+
+// A rule, or the name of a rule set
+struct RuleEntry(Vec<Rule>);
+
+impl<'a> Deserialize<'a> for RuleEntry {
+  fn deserialize<D: Deserializer<'a>>(deserializer: D) -> Result<Self, D::Error> {
+    deserializer.deserialize_any(RuleEntryVisitor)
+  }
+}
+
+struct RuleEntryVisitor;
+impl<'a> Visitor<'a> for RuleEntryVisitor {
+  type Value = RuleEntry;
+
+  fn expecting(&self, formatter: &mut std::fmt::Formatter) -> Result<(), std::fmt::Error> {
+    formatter.write_str("a rule or the name of a rule set")
+  }
+
+  fn visit_str<E: Error>(self, value: &str) -> Result<Self::Value, E> {
+    NAMED
+      .with(|named| named.borrow().rules.get(value).cloned())
+      .map(RuleEntry)
+      .ok_or_else(|| E::custom(format!("unknown rule set: {}", value)))
+  }
+
+  fn visit_map<A: MapAccess<'a>>(self, map: A) -> Result<Self::Value, A::Error> {
+    Rule::deserialize(MapAccessDeserializer::new(map)).map(|rule| RuleEntry(vec![rule]))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::config::write_test_files;
+
+  const DEVICE: &str = "
+devices:
+  - if: { A: b }
+    then:
+      - { from: { key: A, with: [MODS] }, to: { key: B } }
+";
+
+  #[test]
+  fn reads_files_included_twice_once() {
+    let directory = write_test_files(&[
+      ("config.yaml", &format!("version: 1\ninclude: [a.yaml, b.yaml]{}", DEVICE)),
+      ("a.yaml", "include: [common.yaml]"),
+      ("b.yaml", "include: [./common.yaml]"),
+      ("common.yaml", "modifiers: { MODS: [CTRL] }"),
+    ]);
+
+    let config = load(&directory.join("config.yaml"), None).unwrap();
+    assert_eq!(config.devices.len(), 1);
+  }
+
+  #[test]
+  fn rejects_files_including_themselves() {
+    let directory = write_test_files(&[
+      ("config.yaml", "version: 1\ninclude: [a.yaml]\ndevices: []"),
+      ("a.yaml", "include: [config.yaml]"),
+    ]);
+    assert_eq!(
+      load(&directory.join("config.yaml"), None),
+      Err(format!("{} includes itself", directory.join("config.yaml").display()))
+    );
+  }
+
+  #[test]
+  fn resolves_groups_of_groups() {
+    let directory = write_test_files(&[(
+      "config.yaml",
+      &format!("version: 1\nmodifiers: {{ MODS: [NOT_SHIFT, SHIFT], NOT_SHIFT: [CTRL] }}{}", DEVICE),
+    )]);
+    let config = load(&directory.join("config.yaml"), None).unwrap();
+    let with = config.devices[0].then[0].from.with.clone().unwrap();
+    assert_eq!(with.into_iter().collect::<Vec<_>>(), vec![Modifier::SHIFT, Modifier::CTRL]);
+  }
+
+  #[test]
+  fn rejects_groups_containing_themselves() {
+    let directory = write_test_files(&[(
+      "config.yaml",
+      &format!("version: 1\nmodifiers: {{ MODS: [OTHERS], OTHERS: [MODS] }}{}", DEVICE),
+    )]);
+    let error = load(&directory.join("config.yaml"), None).unwrap_err();
+    assert!(error.ends_with("modifiers MODS contains itself"), "{}", error);
+  }
+
+  #[test]
+  fn loads_the_same_config_in_every_format() {
+    let directory = write_test_files(&[
+      (
+        "config.yaml",
+        "
+version: 1
+modifiers: { NOT_SHIFT: [CTRL, ALT, META] }
+devices:
+  - if: { ID_MODEL_ID: \"0007\" }
+    then:
+      - { from: { key: A, without: [NOT_SHIFT] }, to: { key: B, with: [SHIFT] } }
+      - { from: { key: CAPSLOCK }, to: { key: LEFTCTRL }, tap: { key: ESC } }
+",
+      ),
+      (
+        "config.toml",
+        r#"
+version = 1
+modifiers = { NOT_SHIFT = ["CTRL", "ALT", "META"] }
+
+[[devices]]
+if = { ID_MODEL_ID = "0007" }
+then = [
+  { from = { key = "A", without = ["NOT_SHIFT"] }, to = { key = "B", with = ["SHIFT"] } },
+  { from = { key = "CAPSLOCK" }, to = { key = "LEFTCTRL" }, tap = { key = "ESC" } },
+]
+"#,
+      ),
+      (
+        "config.json",
+        r#"{
+  "version": 1,
+  "modifiers": { "NOT_SHIFT": ["CTRL", "ALT", "META"] },
+  "devices": [{
+    "if": { "ID_MODEL_ID": "0007" },
+    "then": [
+      { "from": { "key": "A", "without": ["NOT_SHIFT"] }, "to": { "key": "B", "with": ["SHIFT"] } },
+      { "from": { "key": "CAPSLOCK" }, "to": { "key": "LEFTCTRL" }, "tap": { "key": "ESC" } }
+    ]
+  }]
+}"#,
+      ),
+    ]);
+
+    let yaml = load(&directory.join("config.yaml"), None).unwrap();
+    assert_eq!(load(&directory.join("config.toml"), None), Ok(yaml.clone()));
+    assert_eq!(load(&directory.join("config.json"), None), Ok(yaml));
+  }
+}
@@ -0,0 +1,151 @@
+// What Remapper looks up on every event, computed once per config load
+use super::{EventKey, Modifier, Rule};
+use evdev_rs::enums::EV_KEY;
+use std::collections::{BTreeMap, BTreeSet};
+use std::convert::TryFrom;
+
+#[derive(Debug, Clone, Default, Eq, PartialEq, Ord, PartialOrd)]
+pub(crate) struct KeymapIndex {
+  // Positions of rules in the order Remapper tries them
+  order: Vec<usize>,
+  // Indices of `order` for each from.key
+  by_key: BTreeMap<EventKey, Vec<usize>>,
+  // Keys which rules remap to modifiers
+  modifier_map: BTreeMap<EventKey, Modifier>,
+  // The first rule with tap for each key
+  taps: BTreeMap<EventKey, usize>,
+}
+
+impl KeymapIndex {
+  pub(crate) fn new(rules: &[Rule]) -> Self {
+    // Keys which rules can depend on
+    let mut modifier_keys: BTreeSet<EventKey> = vec![
+      EV_KEY::KEY_LEFTSHIFT,
+      EV_KEY::KEY_RIGHTSHIFT,
+      EV_KEY::KEY_LEFTCTRL,
+      EV_KEY::KEY_RIGHTCTRL,
+      EV_KEY::KEY_LEFTALT,
+      EV_KEY::KEY_RIGHTALT,
+      EV_KEY::KEY_LEFTMETA,
+      EV_KEY::KEY_RIGHTMETA,
+    ]
+    .into_iter()
+    .map(Into::into)
+    .collect();
+    for rule in rules.iter() {
+      for modifier in rule.from.with.iter().chain(rule.from.without.iter()).flatten() {
+        if let Modifier::Key(key) = modifier {
+          modifier_keys.insert(key.clone());
+        }
+      }
+    }
+
+    // Rules pressing modifiers go first, because other rules depend on those modifiers.
+    // Otherwise rules are tried in config order.
+    let (mut order, others): (Vec<usize>, Vec<usize>) =
+      (0..rules.len()).partition(|i| modifier_keys.contains(&rules[*i].to.key));
+    order.extend(others);
+
+    let mut by_key = BTreeMap::new();
+    for (position, i) in order.iter().enumerate() {
+      by_key
+        .entry(rules[*i].from.key.clone())
+        .or_insert_with(Vec::new)
+        .push(position);
+    }
+
+    let mut modifier_map = BTreeMap::new();
+    let mut taps = BTreeMap::new();
+    for (i, rule) in rules.iter().enumerate() {
+      if let Ok(modifier) = Modifier::try_from(&rule.to.key) {
+        modifier_map.insert(rule.from.key.clone(), modifier);
+      }
+      if rule.tap.is_some() {
+        taps.entry(rule.from.key.clone()).or_insert(i);
+      }
+    }
+
+    Self {
+      order,
+      by_key,
+      modifier_map,
+      taps,
+    }
+  }
+
+  pub(crate) fn order(&self) -> &[usize] {
+    &self.order
+  }
+
+  // Positions of rules which may remap any of `keys`, in the order they're tried
+  pub(crate) fn candidates(&self, keys: &[EventKey]) -> Vec<usize> {
+    let mut positions: Vec<usize> = keys
+      .iter()
+      .filter_map(|key| self.by_key.get(key))
+      .flatten()
+      .cloned()
+      .collect();
+    positions.sort_unstable();
+    positions.dedup();
+    positions.into_iter().map(|position| self.order[position]).collect()
+  }
+
+  pub(crate) fn modifier_map(&self) -> &BTreeMap<EventKey, Modifier> {
+    &self.modifier_map
+  }
+
+  pub(crate) fn tap(&self, key: &EventKey) -> Option<usize> {
+    self.taps.get(key).cloned()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn index(yaml: &str) -> KeymapIndex {
+    let rules: Vec<Rule> = serde_yaml::from_str(yaml).unwrap();
+    KeymapIndex::new(&rules)
+  }
+
+  fn key(name: &str) -> EventKey {
+    name.parse().unwrap()
+  }
+
+  const RULES: &str = "
+- { from: { key: J, with: [SPACE] }, to: { key: DOWN } }
+- { from: { key: J }, to: { key: K } }
+- { from: { key: SPACE }, to: { key: SPACE }, tap: { key: SPACE } }
+- { from: { key: CAPSLOCK }, to: { key: LEFTCTRL }, tap: { key: ESC } }
+- { from: { key: CAPSLOCK }, tap: { key: ENTER } }
+";
+
+  #[test]
+  fn tries_rules_to_modifiers_first() {
+    // SPACE is a modifier because rule 1 uses it in `with`
+    assert_eq!(index(RULES).order(), &[2, 3, 0, 1, 4]);
+  }
+
+  #[test]
+  fn finds_candidates_in_order() {
+    let index = index(RULES);
+    assert_eq!(index.candidates(&[key("J")]), vec![0, 1]);
+    assert_eq!(index.candidates(&[key("J"), key("CAPSLOCK")]), vec![3, 0, 1, 4]);
+    assert_eq!(index.candidates(&[key("A")]), Vec::<usize>::new());
+  }
+
+  #[test]
+  fn maps_keys_to_modifiers() {
+    let expected: BTreeMap<EventKey, Modifier> =
+      std::iter::once((key("CAPSLOCK"), Modifier::LEFTCTRL)).collect();
+    assert_eq!(index(RULES).modifier_map(), &expected);
+  }
+
+  #[test]
+  fn finds_first_tap() {
+    let index = index(RULES);
+    assert_eq!(index.tap(&key("CAPSLOCK")), Some(3));
+    assert_eq!(index.tap(&key("SPACE")), Some(2));
+    assert_eq!(index.tap(&key("J")), None);
+  }
+}
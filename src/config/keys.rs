@@ -0,0 +1,179 @@
+// Names of keys in configs and logs, built from evdev's table of key codes
+use super::EventKey;
+use evdev_rs::enums::{int_to_ev_key, EV_KEY};
+use lazy_static::lazy_static;
+use std::collections::BTreeMap;
+
+// Other names people tend to write. Values are canonical names
+const ALIASES: &[(&str, &str)] = &[
+  ("RETURN", "ENTER"),
+  ("ESCAPE", "ESC"),
+  ("DEL", "DELETE"),
+  ("INS", "INSERT"),
+  ("BKSP", "BACKSPACE"),
+  ("PGUP", "PAGEUP"),
+  ("PGDN", "PAGEDOWN"),
+  ("CAPS", "CAPSLOCK"),
+  ("SPC", "SPACE"),
+  ("PRINTSCREEN", "SYSRQ"),
+  ("CTRL", "LEFTCTRL"),
+  ("SHIFT", "LEFTSHIFT"),
+  ("ALT", "LEFTALT"),
+  ("META", "LEFTMETA"),
+  ("SUPER", "LEFTMETA"),
+  ("WIN", "LEFTMETA"),
+  ("LCTRL", "LEFTCTRL"),
+  ("RCTRL", "RIGHTCTRL"),
+  ("LSHIFT", "LEFTSHIFT"),
+  ("RSHIFT", "RIGHTSHIFT"),
+  ("LALT", "LEFTALT"),
+  ("RALT", "RIGHTALT"),
+  ("ALTGR", "RIGHTALT"),
+  ("LMETA", "LEFTMETA"),
+  ("RMETA", "RIGHTMETA"),
+];
+
+lazy_static! {
+  // Canonical names are evdev's ones without KEY_, like ESC or BTN_LEFT
+  static ref NAMES: BTreeMap<u32, String> = {
+    let mut result = BTreeMap::new();
+    for code in 0..=EV_KEY::KEY_MAX as u32 {
+      if let Some(key) = int_to_ev_key(code) {
+        let name = format!("{:?}", key);
+        let name = name.strip_prefix("KEY_").unwrap_or(&name).to_string();
+        result.insert(code, name);
+      }
+    }
+    result
+  };
+
+  // Canonical names and aliases
+  static ref KEYS: BTreeMap<String, EV_KEY> = {
+    let mut result = BTreeMap::new();
+    for (code, name) in NAMES.iter() {
+      result.insert(name.clone(), int_to_ev_key(*code).unwrap());
+    }
+    for (alias, name) in ALIASES.iter() {
+      let key = result[*name].clone();
+      result.insert(alias.to_string(), key);
+    }
+    result
+  };
+}
+
+// Canonical names only
+pub(crate) fn names() -> impl Iterator<Item = &'static str> {
+  NAMES.values().map(String::as_str)
+}
+
+pub(crate) fn name(key: &EV_KEY) -> &'static str {
+  &NAMES[&(key.clone() as u32)]
+}
+
+// Accepts names in any case with or without KEY_, and codes like 0x1d2
+pub(crate) fn parse(value: &str) -> Result<EventKey, String> {
+  let name = value.to_uppercase();
+  let name = name.strip_prefix("KEY_").unwrap_or(&name);
+  if let Some(key) = KEYS.get(name) {
+    return Ok(key.clone().into());
+  }
+
+  if let Some(hex) = name.strip_prefix("0X") {
+    return u32::from_str_radix(hex, 16)
+      .ok()
+      .and_then(int_to_ev_key)
+      .map(EventKey::from)
+      .ok_or_else(|| format!("unknown key code: {}", value));
+  }
+
+  Err(match suggest(name) {
+    Some(suggestion) => format!("unknown key: {}. Did you mean {}?", value, suggestion),
+    None => format!("unknown key: {}", value),
+  })
+}
+
+fn suggest(name: &str) -> Option<&'static str> {
+  KEYS
+    .keys()
+    .map(|candidate| (distance(name, candidate), candidate))
+    .filter(|(distance, _)| *distance <= 2.max(name.len() / 4))
+    .min()
+    .map(|(_, candidate)| candidate.as_str())
+}
+
+// Levenshtein distance
+fn distance(a: &str, b: &str) -> usize {
+  let b: Vec<char> = b.chars().collect();
+  let mut previous: Vec<usize> = (0..=b.len()).collect();
+
+  for (i, a) in a.chars().enumerate() {
+    let mut current = vec![i + 1];
+    for (j, b) in b.iter().enumerate() {
+      let cost = if a == *b { 0 } else { 1 };
+      current.push((previous[j] + cost).min(previous[j + 1] + 1).min(current[j] + 1));
+    }
+    previous = current;
+  }
+
+  previous[b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn esc() -> EventKey {
+    EV_KEY::KEY_ESC.into()
+  }
+
+  #[test]
+  fn parses_names_in_any_case_with_or_without_prefix() {
+    assert_eq!(parse("ESC"), Ok(esc()));
+    assert_eq!(parse("KEY_ESC"), Ok(esc()));
+    assert_eq!(parse("esc"), Ok(esc()));
+    assert_eq!(parse("key_esc"), Ok(esc()));
+  }
+
+  #[test]
+  fn parses_aliases() {
+    assert_eq!(parse("RETURN"), Ok(EV_KEY::KEY_ENTER.into()));
+    assert_eq!(parse("escape"), Ok(esc()));
+    assert_eq!(parse("CTRL"), Ok(EV_KEY::KEY_LEFTCTRL.into()));
+  }
+
+  #[test]
+  fn parses_codes() {
+    assert_eq!(parse("0x1d2"), Ok(EV_KEY::KEY_FN_F1.into()));
+    assert_eq!(parse("0X01"), Ok(esc()));
+    assert_eq!(parse("0xfffff"), Err("unknown key code: 0xfffff".to_string()));
+  }
+
+  #[test]
+  fn suggests_similar_names() {
+    assert_eq!(parse("ESCC"), Err("unknown key: ESCC. Did you mean ESC?".to_string()));
+    assert_eq!(parse("lefctrl"), Err("unknown key: lefctrl. Did you mean LEFTCTRL?".to_string()));
+    assert_eq!(parse("NOTAKEYATALL"), Err("unknown key: NOTAKEYATALL".to_string()));
+  }
+
+  #[test]
+  fn parses_what_it_displays() {
+    for name in names() {
+      let key = parse(name).unwrap();
+      assert_eq!(key.to_string(), name);
+    }
+    for code in 0..=EV_KEY::KEY_MAX as u32 {
+      if let Some(key) = int_to_ev_key(code) {
+        let key = EventKey::from(key);
+        assert_eq!(parse(&key.to_string()), Ok(key));
+      }
+    }
+  }
+
+  #[test]
+  fn measures_distance() {
+    assert_eq!(distance("ESC", "ESC"), 0);
+    assert_eq!(distance("ESCC", "ESC"), 1);
+    assert_eq!(distance("KITTEN", "SITTING"), 3);
+    assert_eq!(distance("", "ABC"), 3);
+  }
+}
@@ -0,0 +1,266 @@
+// Mistakes which don't stop nasskan, but make rules useless. Reported by `nasskan lint`
+use super::{Config, EventKey, KeymapIndex, Modifier, Rule};
+use std::convert::TryFrom;
+
+pub(crate) fn lint(config: &Config) -> Vec<String> {
+  let mut result = Vec::new();
+
+  for (i, device) in config.devices.iter().enumerate() {
+    let device_name = format!("devices[{}]", i);
+    let has_tap = device.keymaps().flatten().any(|rule| rule.tap.is_some());
+    if has_tap && device.tap_timeout == Some(0) {
+      result.push(format!("{}: tap_timeout is 0, so taps never happen", device_name));
+    }
+
+    let keymaps = std::iter::once(("then".to_string(), &device.then)).chain(
+      device
+        .profiles
+        .iter()
+        .map(|(name, rules)| (format!("profiles.{}", name), rules)),
+    );
+    for (keymap_name, keymap) in keymaps {
+      for (j, problem) in lint_keymap(keymap) {
+        result.push(format!(
+          "{} {} rule {} ({}): {}",
+          device_name,
+          keymap_name,
+          j + 1,
+          describe(&keymap[j]),
+          problem
+        ));
+      }
+    }
+  }
+
+  result
+}
+
+// Indices of rules with their problems
+fn lint_keymap(keymap: &[Rule]) -> Vec<(usize, String)> {
+  let mut result = Vec::new();
+
+  // Rules are tried in the same order as Remapper does
+  let index = KeymapIndex::new(keymap);
+  let order = index.order();
+
+  for (position, j) in order.iter().enumerate() {
+    let rule = &keymap[*j];
+    let earlier = order[..position]
+      .iter()
+      .find(|i| keymap[**i].from.key == rule.from.key && covers(&keymap[**i], rule));
+    match earlier {
+      Some(i) if keymap[*i] == *rule => {
+        result.push((*j, format!("the same as rule {}", i + 1)));
+      }
+      Some(i) => {
+        result.push((*j, format!("never used, because rule {} matches first", i + 1)));
+      }
+      None => {}
+    }
+  }
+
+  for (j, rule) in keymap.iter().enumerate() {
+    for with in rule.from.with.iter().flatten() {
+      let contradiction = rule
+        .from
+        .without
+        .iter()
+        .flatten()
+        .find(|without| implies(with, without));
+      if let Some(without) = contradiction {
+        result.push((
+          j,
+          format!("never used, because with {} contradicts without {}", with, without),
+        ));
+      }
+    }
+
+    // Remapper only counts modifiers which some rule remapped, so `LEFTSHIFT -> LEFTSHIFT`
+    // is what makes `with: [LEFTSHIFT]` work. Keys used in `with` or `without` are the same.
+    if rule.to.key == rule.from.key
+      && rule.to.with.clone().unwrap_or_default() == rule.from.with.clone().unwrap_or_default()
+      && rule.tap.is_none()
+      && rule.repeat.is_none()
+      && Modifier::try_from(&rule.from.key).is_err()
+      && !is_used_as_modifier(keymap, &rule.from.key)
+    {
+      result.push((j, "to is the same as from".to_string()));
+    }
+
+    // Remapper only looks at the first tap for each key
+    if rule.tap.is_some() {
+      let earlier = keymap[..j]
+        .iter()
+        .position(|other| other.tap.is_some() && other.from.key == rule.from.key);
+      if let Some(i) = earlier {
+        result.push((j, format!("tap is never used, because rule {} has one for the key", i + 1)));
+      }
+    }
+  }
+
+  result.sort();
+  result
+}
+
+fn is_used_as_modifier(keymap: &[Rule], key: &EventKey) -> bool {
+  keymap
+    .iter()
+    .flat_map(|rule| rule.from.with.iter().chain(rule.from.without.iter()).flatten())
+    .any(|modifier| *modifier == Modifier::Key(key.clone()))
+}
+
+// Whether `earlier` matches whenever `later` does
+fn covers(earlier: &Rule, later: &Rule) -> bool {
+  let later_with = later.from.with.clone().unwrap_or_default();
+  let later_without = later.from.without.clone().unwrap_or_default();
+
+  earlier
+    .from
+    .with
+    .iter()
+    .flatten()
+    .all(|modifier| later_with.iter().any(|held| implies(held, modifier)))
+    && earlier
+      .from
+      .without
+      .iter()
+      .flatten()
+      .all(|modifier| later_without.iter().any(|released| implies(modifier, released)))
+}
+
+// Whether `modifier` is held whenever `held` is, e.g. SHIFT is held whenever LEFTSHIFT is
+fn implies(held: &Modifier, modifier: &Modifier) -> bool {
+  let sides = modifier.sides();
+  held.sides().iter().all(|side| sides.contains(side))
+}
+
+fn describe(rule: &Rule) -> String {
+  let mut names: Vec<String> = rule
+    .from
+    .with
+    .iter()
+    .flatten()
+    .map(|modifier| modifier.to_string())
+    .collect();
+  names.push(rule.from.key.to_string());
+  names.join("+")
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::config::{load_from, write_test_files};
+
+  fn lint_rules(yaml: &str) -> Vec<(usize, String)> {
+    let keymap: Vec<Rule> = serde_yaml::from_str(yaml).unwrap();
+    lint_keymap(&keymap)
+  }
+
+  fn problem(i: usize, message: &str) -> (usize, String) {
+    (i, message.to_string())
+  }
+
+  #[test]
+  fn finds_shadowed_rules() {
+    let problems = lint_rules(
+      "
+- { from: { key: A }, to: { key: B } }
+- { from: { key: A, with: [SHIFT] }, to: { key: C } }
+",
+    );
+    assert_eq!(problems, vec![problem(1, "never used, because rule 1 matches first")]);
+  }
+
+  #[test]
+  fn finds_duplicates() {
+    let problems = lint_rules(
+      "
+- { from: { key: A, with: [CTRL] }, to: { key: B } }
+- { from: { key: A, with: [CTRL] }, to: { key: B } }
+",
+    );
+    assert_eq!(problems, vec![problem(1, "the same as rule 1")]);
+  }
+
+  #[test]
+  fn finds_with_contradicting_without() {
+    let problems = lint_rules(
+      "
+- { from: { key: X, with: [LEFTSHIFT], without: [SHIFT] }, to: { key: C } }
+- { from: { key: Y, with: [SHIFT], without: [LEFTSHIFT] }, to: { key: C } }
+",
+    );
+    assert_eq!(
+      problems,
+      vec![problem(0, "never used, because with LEFTSHIFT contradicts without SHIFT")]
+    );
+  }
+
+  #[test]
+  fn finds_rules_to_themselves() {
+    let problems = lint_rules("[{ from: { key: Z }, to: { key: Z } }]");
+    assert_eq!(problems, vec![problem(0, "to is the same as from")]);
+  }
+
+  #[test]
+  fn keeps_modifier_rules_to_themselves() {
+    let problems = lint_rules(
+      "
+- { from: { key: LEFTSHIFT }, to: { key: LEFTSHIFT } }
+- { from: { key: A, with: [LEFTSHIFT] }, to: { key: B } }
+",
+    );
+    assert_eq!(problems, vec![]);
+  }
+
+  #[test]
+  fn keeps_rules_to_themselves_for_keys_used_as_modifiers() {
+    let problems = lint_rules(
+      "
+- { from: { key: SPACE }, to: { key: SPACE } }
+- { from: { key: J, with: [SPACE] }, to: { key: DOWN } }
+- { from: { key: Z }, to: { key: Z } }
+- { from: { key: K, without: [Z] }, to: { key: UP } }
+",
+    );
+    assert_eq!(problems, vec![]);
+  }
+
+  #[test]
+  fn finds_second_taps_for_a_key() {
+    let problems = lint_rules(
+      "
+- { from: { key: CAPSLOCK }, to: { key: LEFTCTRL }, tap: { key: ESC } }
+- { from: { key: CAPSLOCK }, tap: { key: ENTER } }
+",
+    );
+    assert_eq!(
+      problems,
+      vec![
+        problem(1, "never used, because rule 1 matches first"),
+        problem(1, "tap is never used, because rule 1 has one for the key"),
+      ]
+    );
+  }
+
+  #[test]
+  fn finds_taps_with_zero_timeout() {
+    let directory = write_test_files(&[(
+      "config.yaml",
+      "
+version: 2
+devices:
+  - if: { A: b }
+    timing: { tap_timeout: 0 }
+    layers:
+      - rules:
+          - { from: { key: CAPSLOCK }, to: { key: LEFTCTRL }, tap: { key: ESC } }
+",
+    )]);
+    let config = load_from(&directory.join("config.yaml"), None).unwrap();
+    assert_eq!(
+      lint(&config),
+      vec!["devices[0]: tap_timeout is 0, so taps never happen".to_string()]
+    );
+  }
+}
@@ -0,0 +1,268 @@
+// `nasskan migrate-config`. Unlike `to_yaml`, this rewrites the file as written:
+// `include`, `modifiers`, `rules`, side-agnostic modifiers and key names stay as they are.
+// Keys are Strings here, because serde_yaml's Value would turn IDs like 0007 into 7.
+use super::{expand, Bypass, Panic, Repeat, Side, Stats};
+use serde::de::value::MapAccessDeserializer;
+use serde::de::{Error, MapAccess, Visitor};
+use serde::{Deserialize, Deserializer, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+// Included files have no `version`, and they're migrated the same way
+pub(crate) fn migrate(path: &Path) -> Result<String, String> {
+  let content = std::fs::read_to_string(path)
+    .map_err(|error| format!("{} could not be opened: {}", path.display(), error))?;
+  let version: Version = expand::parse(path, &content)?;
+  match version.version {
+    Some(1) => {
+      // Catches mistakes the raw shape can't, like unknown keys or rule sets
+      super::load_from(path, None)?;
+    }
+    None => {}
+    Some(version) => {
+      return Err(format!("{} is version {}, which can't be migrated", path.display(), version))
+    }
+  }
+
+  let document: Document<V1Device> = expand::parse(path, &content)?;
+  let migrated: Document<V2Device> = document.into();
+  Ok(serde_yaml::to_string(&migrated).unwrap())
+}
+
+#[derive(Deserialize)]
+struct Version {
+  version: Option<u8>,
+}
+
+#[derive(Deserialize, Serialize)]
+struct Document<D> {
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  version: Option<u8>,
+  #[serde(default, skip_serializing_if = "Vec::is_empty")]
+  include: Vec<PathBuf>,
+  #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+  modifiers: BTreeMap<String, Vec<String>>,
+  #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+  rules: BTreeMap<String, Vec<RuleEntry>>,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  panic: Option<Panic>,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  bypass: Option<Bypass>,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  default_side: Option<Side>,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  stats: Option<Stats>,
+  #[serde(default = "Vec::new")]
+  devices: Vec<D>,
+}
+
+#[derive(Deserialize)]
+struct V1Device {
+  #[serde(rename = "if")]
+  if_: BTreeMap<String, String>,
+  then: Vec<RuleEntry>,
+  #[serde(default)]
+  profiles: BTreeMap<String, Vec<RuleEntry>>,
+  repeat: Option<Repeat>,
+  #[serde(default)]
+  split_modifiers: bool,
+}
+
+#[derive(Serialize)]
+struct V2Device {
+  #[serde(rename = "if")]
+  if_: BTreeMap<String, String>,
+  #[serde(skip_serializing_if = "Timing::is_empty")]
+  timing: Timing,
+  layers: Vec<Layer>,
+}
+
+#[derive(Serialize)]
+struct Timing {
+  #[serde(skip_serializing_if = "Option::is_none")]
+  repeat: Option<Repeat>,
+  #[serde(skip_serializing_if = "std::ops::Not::not")]
+  split_modifiers: bool,
+}
+
+impl Timing {
+  fn is_empty(&self) -> bool {
+    self.repeat.is_none() && !self.split_modifiers
+  }
+}
+
+#[derive(Serialize)]
+struct Layer {
+  #[serde(skip_serializing_if = "Option::is_none")]
+  when: Option<When>,
+  rules: Vec<RuleEntry>,
+}
+
+#[derive(Serialize)]
+struct When {
+  context: String,
+}
+
+impl From<Document<V1Device>> for Document<V2Device> {
+  fn from(document: Document<V1Device>) -> Self {
+    Self {
+      version: document.version.map(|_| 2),
+      include: document.include,
+      modifiers: document.modifiers,
+      rules: document.rules,
+      panic: document.panic,
+      bypass: document.bypass,
+      default_side: document.default_side,
+      stats: document.stats,
+      devices: document.devices.into_iter().map(Into::into).collect(),
+    }
+  }
+}
+
+impl From<V1Device> for V2Device {
+  fn from(device: V1Device) -> Self {
+    let base = Layer {
+      when: None,
+      rules: device.then,
+    };
+    let profiles = device.profiles.into_iter().map(|(context, rules)| Layer {
+      when: Some(When { context }),
+      rules,
+    });
+
+    Self {
+      if_: device.if_,
+      timing: Timing {
+        repeat: device.repeat,
+        split_modifiers: device.split_modifiers,
+      },
+      layers: std::iter::once(base).chain(profiles).collect(),
+    }
+  }
+}
+
+#[derive(Deserialize, Serialize)]
+struct Rule {
+  from: From_,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  to: Option<To>,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  tap: Option<Tap>,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  repeat: Option<bool>,
+}
+
+#[derive(Deserialize, Serialize)]
+struct From_ {
+  key: String,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  with: Option<Vec<String>>,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  without: Option<Vec<String>>,
+}
+
+#[derive(Deserialize, Serialize)]
+struct To {
+  key: String,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  with: Option<Vec<String>>,
+}
+
+#[derive(Deserialize, Serialize)]
+struct Tap {
+  key: String,
+}
+
+// A rule, or the name of a rule set.
+// Deserialized by hand, because an untagged enum would make serde_yaml read `key: 1` as a number.
+#[derive(Serialize)]
+#[serde(untagged)]
+enum RuleEntry {
+  Name(String),
+  Rule(Rule),
+}
+
+impl<'a> Deserialize<'a> for RuleEntry {
+  fn deserialize<D: Deserializer<'a>>(deserializer: D) -> Result<Self, D::Error> {
+    deserializer.deserialize_any(RuleEntryVisitor)
+  }
+}
+
+struct RuleEntryVisitor;
+impl<'a> Visitor<'a> for RuleEntryVisitor {
+  type Value = RuleEntry;
+
+  fn expecting(&self, formatter: &mut std::fmt::Formatter) -> Result<(), std::fmt::Error> {
+    formatter.write_str("a rule or the name of a rule set")
+  }
+
+  fn visit_str<E: Error>(self, value: &str) -> Result<Self::Value, E> {
+    Ok(RuleEntry::Name(value.to_string()))
+  }
+
+  fn visit_map<A: MapAccess<'a>>(self, map: A) -> Result<Self::Value, A::Error> {
+    Rule::deserialize(MapAccessDeserializer::new(map)).map(RuleEntry::Rule)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::config::{load_from, write_test_files};
+
+  #[test]
+  fn migrates_to_the_same_config() {
+    let original = write_test_files(&[
+      (
+        "config.yaml",
+        "
+version: 1
+include: [common.yaml]
+modifiers:
+  NOT_SHIFT: [CTRL, ALT, META]
+default_side: RIGHT
+devices:
+  - if: { ID_MODEL_ID: 0007 }
+    then:
+      - caps
+      - { from: { key: 1, without: [NOT_SHIFT] }, to: { key: 2, with: [SHIFT] } }
+    profiles:
+      firefox:
+        - caps
+        - { from: { key: F1 }, to: { key: ESC }, repeat: false }
+    repeat: { delay: 200, rate: 30 }
+    split_modifiers: true
+",
+      ),
+      (
+        "common.yaml",
+        "
+rules:
+  caps:
+    - { from: { key: CAPSLOCK }, to: { key: LEFTCTRL }, tap: { key: ESC } }
+devices:
+  - if: { ID_MODEL_ID: 0x10 }
+    then: [caps]
+",
+      ),
+    ]);
+    let migrated = write_test_files(&[
+      ("config.yaml", &migrate(&original.join("config.yaml")).unwrap()),
+      ("common.yaml", &migrate(&original.join("common.yaml")).unwrap()),
+    ]);
+
+    let mut expected = load_from(&original.join("config.yaml"), None).unwrap();
+    expected.version = 2;
+    assert_eq!(load_from(&migrated.join("config.yaml"), None), Ok(expected));
+  }
+
+  #[test]
+  fn refuses_version_2() {
+    let directory = write_test_files(&[("config.yaml", "version: 2\ndevices: []")]);
+    let path = directory.join("config.yaml");
+    assert_eq!(
+      migrate(&path),
+      Err(format!("{} is version 2, which can't be migrated", path.display()))
+    );
+  }
+}
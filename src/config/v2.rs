@@ -0,0 +1,169 @@
+// Version 2 of the config format. It's converted to `Config`, which has the shape of version 1
+use super::{expand, Bypass, Modifier, Panic, Repeat, Rule, Side, Stats};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+
+#[derive(Deserialize, Serialize, JsonSchema, Debug)]
+pub(crate) struct Config {
+  pub(super) version: u8,
+  #[serde(default)]
+  panic: Panic,
+  #[serde(default)]
+  bypass: Bypass,
+  #[serde(default)]
+  default_side: Side,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  stats: Option<Stats>,
+  pub(super) devices: Vec<Device>,
+}
+
+#[derive(Deserialize, Serialize, JsonSchema, Debug)]
+pub(super) struct Device {
+  #[serde(rename = "if")]
+  if_: BTreeMap<String, String>,
+  #[serde(default, skip_serializing_if = "Timing::is_default")]
+  timing: Timing,
+  layers: Vec<Layer>,
+}
+
+#[derive(Deserialize, Serialize, JsonSchema, Debug, Default, PartialEq)]
+struct Timing {
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  repeat: Option<Repeat>,
+  // Releasing a key after this many milliseconds is not a tap
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  tap_timeout: Option<u64>,
+  // Modifier changes are sent in their own frames
+  #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+  split_modifiers: bool,
+}
+
+impl Timing {
+  fn is_default(&self) -> bool {
+    self == &Self::default()
+  }
+}
+
+// Layers without `when.context` are `then` of version 1, and ones with it are `profiles`
+#[derive(Deserialize, Serialize, JsonSchema, Debug)]
+struct Layer {
+  // Only for humans
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  name: Option<String>,
+  #[serde(default, skip_serializing_if = "When::is_default")]
+  when: When,
+  #[serde(deserialize_with = "expand::rules")]
+  #[schemars(schema_with = "expand::rules_schema")]
+  rules: Vec<Rule>,
+}
+
+#[derive(Deserialize, Serialize, JsonSchema, Debug, Default, PartialEq)]
+struct When {
+  // Rules replace layers without context while the context matches
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  context: Option<String>,
+  // Added to `from.with` of every rule
+  #[serde(
+    default,
+    deserialize_with = "expand::modifiers",
+    skip_serializing_if = "Option::is_none"
+  )]
+  held: Option<BTreeSet<Modifier>>,
+}
+
+impl When {
+  fn is_default(&self) -> bool {
+    self == &Self::default()
+  }
+}
+
+impl From<Config> for super::Config {
+  fn from(config: Config) -> Self {
+    Self {
+      version: config.version,
+      panic: config.panic,
+      bypass: config.bypass,
+      default_side: config.default_side,
+      stats: config.stats,
+      devices: config.devices.into_iter().map(Into::into).collect(),
+    }
+  }
+}
+
+impl From<Device> for super::Device {
+  fn from(device: Device) -> Self {
+    let mut then = Vec::new();
+    let mut profiles = BTreeMap::new();
+    for layer in device.layers {
+      let held = layer.when.held.unwrap_or_default();
+      let rules = layer.rules.into_iter().map(|mut rule| {
+        if !held.is_empty() {
+          let with = rule.from.with.get_or_insert_with(BTreeSet::new);
+          with.extend(held.iter().cloned());
+        }
+        rule
+      });
+
+      match layer.when.context {
+        None => then.extend(rules),
+        Some(context) => profiles
+          .entry(context)
+          .or_insert_with(Vec::new)
+          .extend(rules),
+      }
+    }
+
+    Self {
+      if_: device.if_,
+      then,
+      profiles,
+      repeat: device.timing.repeat,
+      tap_timeout: device.timing.tap_timeout,
+      split_modifiers: device.timing.split_modifiers,
+      indexes: BTreeMap::new(),
+    }
+  }
+}
+
+// For migration. Includes and names have been expanded already
+impl From<&super::Config> for Config {
+  fn from(config: &super::Config) -> Self {
+    Self {
+      version: 2,
+      panic: config.panic.clone(),
+      bypass: config.bypass.clone(),
+      default_side: config.default_side,
+      stats: config.stats.clone(),
+      devices: config.devices.iter().map(Into::into).collect(),
+    }
+  }
+}
+
+impl From<&super::Device> for Device {
+  fn from(device: &super::Device) -> Self {
+    let base = Layer {
+      name: None,
+      when: When::default(),
+      rules: device.then.clone(),
+    };
+    let profiles = device.profiles.iter().map(|(context, rules)| Layer {
+      name: None,
+      when: When {
+        context: Some(context.clone()),
+        held: None,
+      },
+      rules: rules.clone(),
+    });
+
+    Self {
+      if_: device.if_.clone(),
+      timing: Timing {
+        repeat: device.repeat.clone(),
+        tap_timeout: device.tap_timeout,
+        split_modifiers: device.split_modifiers,
+      },
+      layers: std::iter::once(base).chain(profiles).collect(),
+    }
+  }
+}
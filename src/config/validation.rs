@@ -1,22 +1,8 @@
-use super::{Config, Modifier};
-use std::convert::TryInto;
+use super::Config;
 
-pub(crate) fn validate_order(config: &Config) {
-  for device in config.devices.iter() {
-    let mut key_found = false;
-    for rule in device.then.iter() {
-      if ((&rule.to.key).try_into().ok() as Option<Modifier>).is_none() {
-        key_found = true;
-      } else if key_found {
-        panic!("Remap rules for modifiers should be at first");
-      }
-    }
-  }
-}
-
-pub(crate) fn validate_tap(config: &Config) {
-  for device in config.devices.iter() {
-    for rule in device.then.iter() {
+pub(crate) fn validate_tap(config: &Config) -> Result<(), String> {
+  for keymap in config.devices.iter().flat_map(|device| device.keymaps()) {
+    for rule in keymap.iter() {
       if rule.tap.is_some()
         && (rule
           .from
@@ -37,8 +23,43 @@ pub(crate) fn validate_tap(config: &Config) {
             .map(|modifiers| 0 < modifiers.len())
             .unwrap_or(false))
       {
-        panic!("Remap rules with tap should not have from.with, from.without or to.with clause");
+        return Err(
+          "Remap rules with tap should not have from.with, from.without or to.with clause"
+            .to_string(),
+        );
+      }
+    }
+  }
+
+  Ok(())
+}
+
+pub(crate) fn validate_repeat(config: &Config) -> Result<(), String> {
+  for device in config.devices.iter() {
+    if let Some(repeat) = &device.repeat {
+      if repeat.rate == 0 {
+        return Err("repeat.rate should be greater than 0".to_string());
+      }
+      // Faster than this, nasskan would do nothing but repeating
+      if repeat.rate > 1000 {
+        return Err("repeat.rate should be 1000 or less".to_string());
       }
     }
   }
+
+  Ok(())
+}
+
+pub(crate) fn validate_action(config: &Config) -> Result<(), String> {
+  let has_action = config
+    .devices
+    .iter()
+    .flat_map(|device| device.keymaps())
+    .flatten()
+    .any(|rule| rule.action.is_some());
+  if config.version == 1 && has_action {
+    return Err("action is only in version 2".to_string());
+  }
+
+  Ok(())
 }
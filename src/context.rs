@@ -0,0 +1,213 @@
+use crate::socket::{self, LineStream};
+use crate::{AsyncWorker, WorkerManager};
+use log::*;
+use std::convert::TryInto;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::Path;
+
+// Each line written to this socket is a context, usually the class of the focused application.
+// An empty line means no context.
+pub(crate) const SOCKET_PATH: &str = "/run/nasskan/context.sock";
+
+pub(crate) struct ContextListenerWorker {
+  listener: UnixListener,
+}
+
+impl ContextListenerWorker {
+  pub(crate) fn new() -> Self {
+    // Context providers run in users' sessions. They can only select profiles written in the config
+    let listener =
+      socket::bind(Path::new(SOCKET_PATH), 0o666).expect("Binding the context socket failed");
+
+    Self { listener }
+  }
+}
+
+impl AsRawFd for ContextListenerWorker {
+  fn as_raw_fd(&self) -> RawFd {
+    self.listener.as_raw_fd()
+  }
+}
+
+impl AsyncWorker for ContextListenerWorker {
+  fn step(&mut self, manager: &mut WorkerManager) {
+    for stream in socket::accept_all(&self.listener) {
+      manager.spawn(ContextClientWorker {
+        stream: LineStream::new(stream),
+      });
+    }
+  }
+}
+
+struct ContextClientWorker {
+  stream: LineStream,
+}
+
+impl AsRawFd for ContextClientWorker {
+  fn as_raw_fd(&self) -> RawFd {
+    self.stream.as_raw_fd()
+  }
+}
+
+impl AsyncWorker for ContextClientWorker {
+  fn step(&mut self, manager: &mut WorkerManager) {
+    for line in self.stream.read_lines() {
+      let context = line.trim();
+      manager.set_context(if context.is_empty() {
+        None
+      } else {
+        Some(context.to_string())
+      });
+    }
+  }
+
+  fn is_finished(&self) -> bool {
+    self.stream.is_closed()
+  }
+}
+
+// Runs in a user's session, and follows the focused application of the compositor
+trait ContextProvider {
+  // Blocks until the focused application changes
+  fn next_context(&mut self) -> std::io::Result<Option<String>>;
+}
+
+pub(crate) fn provide(name: &str) {
+  let mut provider: Box<dyn ContextProvider> = match name {
+    "sway" => Box::new(Sway::connect().expect("Connecting to sway failed")),
+    "hyprland" => Box::new(Hyprland::connect().expect("Connecting to Hyprland failed")),
+    _ => {
+      eprintln!("Unknown context provider: {}", name);
+      std::process::exit(2);
+    }
+  };
+  let mut daemon = UnixStream::connect(SOCKET_PATH)
+    .expect("Connecting to nasskan failed. Maybe no device in your config has profiles?");
+
+  loop {
+    let context = provider
+      .next_context()
+      .expect("Lost connection to the compositor");
+    info!("Focused: {:?}", context);
+    writeln!(daemon, "{}", context.unwrap_or_default()).expect("Lost connection to nasskan");
+  }
+}
+
+// https://github.com/swaywm/sway/blob/master/sway/sway-ipc.7.scd
+struct Sway {
+  stream: UnixStream,
+}
+
+impl Sway {
+  const MAGIC: &'static [u8] = b"i3-ipc";
+  const SUBSCRIBE: u32 = 2;
+  const WINDOW_EVENT: u32 = 0x8000_0003;
+
+  fn connect() -> std::io::Result<Self> {
+    let path = std::env::var("SWAYSOCK").map_err(|_| {
+      std::io::Error::new(std::io::ErrorKind::NotFound, "SWAYSOCK is not set")
+    })?;
+    let mut sway = Self {
+      stream: UnixStream::connect(path)?,
+    };
+
+    sway.send(Self::SUBSCRIBE, br#"["window"]"#)?;
+    sway.receive()?;
+    Ok(sway)
+  }
+
+  fn send(&mut self, message_type: u32, payload: &[u8]) -> std::io::Result<()> {
+    let mut message = Self::MAGIC.to_vec();
+    message.extend_from_slice(&(payload.len() as u32).to_ne_bytes());
+    message.extend_from_slice(&message_type.to_ne_bytes());
+    message.extend_from_slice(payload);
+    self.stream.write_all(&message)
+  }
+
+  fn receive(&mut self) -> std::io::Result<(u32, serde_json::Value)> {
+    let mut header = [0; 14];
+    self.stream.read_exact(&mut header)?;
+    if &header[..6] != Self::MAGIC {
+      return Err(std::io::Error::new(
+        std::io::ErrorKind::InvalidData,
+        "Not a sway IPC message",
+      ));
+    }
+
+    let length = u32::from_ne_bytes(header[6..10].try_into().unwrap());
+    let message_type = u32::from_ne_bytes(header[10..14].try_into().unwrap());
+    let mut payload = vec![0; length as usize];
+    self.stream.read_exact(&mut payload)?;
+
+    Ok((message_type, serde_json::from_slice(&payload)?))
+  }
+}
+
+impl ContextProvider for Sway {
+  fn next_context(&mut self) -> std::io::Result<Option<String>> {
+    loop {
+      let (message_type, event) = self.receive()?;
+      if message_type != Self::WINDOW_EVENT || event["change"] != "focus" {
+        continue;
+      }
+
+      // app_id is for Wayland-native applications, class is for XWayland ones
+      let container = &event["container"];
+      let class = container["app_id"]
+        .as_str()
+        .or_else(|| container["window_properties"]["class"].as_str());
+      return Ok(class.map(String::from));
+    }
+  }
+}
+
+// https://wiki.hyprland.org/IPC/
+struct Hyprland {
+  events: BufReader<UnixStream>,
+}
+
+impl Hyprland {
+  fn connect() -> std::io::Result<Self> {
+    let signature = std::env::var("HYPRLAND_INSTANCE_SIGNATURE").map_err(|_| {
+      std::io::Error::new(
+        std::io::ErrorKind::NotFound,
+        "HYPRLAND_INSTANCE_SIGNATURE is not set",
+      )
+    })?;
+    let runtime_dir = std::env::var("XDG_RUNTIME_DIR").unwrap_or_else(|_| "/tmp".to_string());
+
+    // Older versions of Hyprland put the socket in /tmp
+    let stream = UnixStream::connect(format!(
+      "{}/hypr/{}/.socket2.sock",
+      runtime_dir, signature
+    ))
+    .or_else(|_| UnixStream::connect(format!("/tmp/hypr/{}/.socket2.sock", signature)))?;
+
+    Ok(Self {
+      events: BufReader::new(stream),
+    })
+  }
+}
+
+impl ContextProvider for Hyprland {
+  fn next_context(&mut self) -> std::io::Result<Option<String>> {
+    loop {
+      let mut line = String::new();
+      if self.events.read_line(&mut line)? == 0 {
+        return Err(std::io::ErrorKind::UnexpectedEof.into());
+      }
+
+      // activewindow>>CLASS,TITLE
+      if let Some(window) = line.trim_end().strip_prefix("activewindow>>") {
+        let class = window.split(',').next().unwrap_or("");
+        return Ok(if class.is_empty() {
+          None
+        } else {
+          Some(class.to_string())
+        });
+      }
+    }
+  }
+}
@@ -0,0 +1,170 @@
+use crate::remapper::KeyState;
+use crate::socket::{self, LineStream};
+use crate::systemd;
+use crate::{AsyncWorker, WorkerManager};
+use serde::Deserialize;
+use serde_json::json;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::os::unix::net::UnixListener;
+use std::path::Path;
+
+// Speaks JSON lines. Each request line gets exactly one response line
+pub(crate) const SOCKET_PATH: &str = "/run/nasskan/control.sock";
+
+#[derive(Deserialize, Debug)]
+#[serde(tag = "command", rename_all = "kebab-case")]
+enum Request {
+  // Connected keyboards and devices sections they matched
+  List,
+  // What each remapper thinks is pressed
+  State,
+  // Same as writing a line to the context socket
+  Profile { profile: Option<String> },
+  Pause,
+  Resume,
+  Reload,
+  // Aggregated counts of keys and rules. Needs `stats` in the config
+  Stats,
+  // Starts or stops explaining remaps. Without `enabled`, returns the explanations so far
+  Explain { enabled: Option<bool> },
+}
+
+pub(crate) struct ControlListenerWorker {
+  listener: UnixListener,
+}
+
+impl ControlListenerWorker {
+  pub(crate) fn new() -> Self {
+    // Only root can reload the config or pause remapping
+    let listener = systemd::listened_socket().unwrap_or_else(|| {
+      socket::bind(Path::new(SOCKET_PATH), 0o600).expect("Binding the control socket failed")
+    });
+
+    Self { listener }
+  }
+}
+
+impl AsRawFd for ControlListenerWorker {
+  fn as_raw_fd(&self) -> RawFd {
+    self.listener.as_raw_fd()
+  }
+}
+
+impl AsyncWorker for ControlListenerWorker {
+  fn step(&mut self, manager: &mut WorkerManager) {
+    for stream in socket::accept_all(&self.listener) {
+      manager.spawn(ControlClientWorker {
+        stream: LineStream::new(stream),
+      });
+    }
+  }
+}
+
+struct ControlClientWorker {
+  stream: LineStream,
+}
+
+impl AsRawFd for ControlClientWorker {
+  fn as_raw_fd(&self) -> RawFd {
+    self.stream.as_raw_fd()
+  }
+}
+
+impl AsyncWorker for ControlClientWorker {
+  fn step(&mut self, manager: &mut WorkerManager) {
+    for line in self.stream.read_lines() {
+      let response = match serde_json::from_str(&line) {
+        Ok(request) => handle(request, manager),
+        Err(error) => Err(format!("invalid request: {}", error)),
+      };
+
+      let response = match response {
+        Ok(mut result) => {
+          result["ok"] = json!(true);
+          result
+        }
+        Err(error) => json!({ "ok": false, "error": error }),
+      };
+      self.stream.write_line(&response.to_string());
+    }
+  }
+
+  fn is_finished(&self) -> bool {
+    self.stream.is_closed()
+  }
+}
+
+fn handle(request: Request, manager: &mut WorkerManager) -> Result<serde_json::Value, String> {
+  match request {
+    Request::List => {
+      let mut keyboards = Vec::new();
+      manager.for_each_keyboard(|id, keyboard| {
+        keyboards.push(json!({
+          "id": id,
+          "devnode": keyboard.devnode,
+          "if": keyboard.config_device.if_,
+          "paused": keyboard.is_paused(),
+        }))
+      });
+
+      Ok(json!({ "keyboards": keyboards }))
+    }
+    Request::State => {
+      let mut keyboards = Vec::new();
+      manager.for_each_keyboard(|id, keyboard| {
+        let keyboard_state: Vec<serde_json::Value> = keyboard
+          .remapper
+          .keyboard_state()
+          .iter()
+          .map(|key_state| match key_state {
+            KeyState::Passthru(key) => json!({ "passthru": key.to_string() }),
+            KeyState::Remapped(rule) => json!({
+              "remapped": { "from": rule.from.key.to_string(), "to": rule.to.key.to_string() }
+            }),
+          })
+          .collect();
+
+        keyboards.push(json!({ "id": id, "keyboard_state": keyboard_state }))
+      });
+
+      Ok(json!({ "context": manager.context, "keyboards": keyboards }))
+    }
+    Request::Profile { profile } => {
+      manager.set_context(profile);
+      Ok(json!({}))
+    }
+    Request::Pause => {
+      manager.for_each_keyboard(|_, keyboard| keyboard.pause());
+      Ok(json!({}))
+    }
+    Request::Resume => {
+      manager.for_each_keyboard(|_, keyboard| keyboard.resume());
+      Ok(json!({}))
+    }
+    Request::Reload => {
+      manager.reload()?;
+      Ok(json!({}))
+    }
+    Request::Stats => {
+      if manager.config.stats.is_none() {
+        return Err("stats is not enabled in the config".to_string());
+      }
+      Ok(manager.counters().to_json())
+    }
+    Request::Explain {
+      enabled: Some(enabled),
+    } => {
+      manager.for_each_keyboard(|_, keyboard| keyboard.remapper.set_explaining(enabled));
+      Ok(json!({}))
+    }
+    Request::Explain { enabled: None } => {
+      let mut keyboards = Vec::new();
+      manager.for_each_keyboard(|id, keyboard| {
+        let explanation = keyboard.remapper.take_explanation();
+        keyboards.push(json!({ "id": id, "explanation": explanation }))
+      });
+
+      Ok(json!({ "keyboards": keyboards }))
+    }
+  }
+}
@@ -0,0 +1,52 @@
+use log::LevelFilter;
+use serde_json::json;
+use std::fmt::{Debug, Formatter};
+use std::io::Write;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+// Key identities appear in logs only if NASSKAN_LOG_KEYS=1, since otherwise the journal becomes a keylogger.
+// They're omitted rather than hashed, because hashes of ~100 keys are easily reversed by frequency analysis.
+static LOGS_KEYS: AtomicBool = AtomicBool::new(false);
+
+pub(crate) fn init() {
+  LOGS_KEYS.store(
+    std::env::var("NASSKAN_LOG_KEYS").as_deref() == Ok("1"),
+    Ordering::Relaxed,
+  );
+
+  let mut builder = env_logger::Builder::new();
+  match std::env::var("RUST_LOG") {
+    Ok(filters) => builder.parse_filters(&filters),
+    Err(_) => builder.filter_level(LevelFilter::Info),
+  };
+
+  // One JSON object per line, for journald or log collectors
+  if std::env::var("NASSKAN_LOG_FORMAT").as_deref() == Ok("json") {
+    builder.format(|buf, record| {
+      writeln!(
+        buf,
+        "{}",
+        json!({
+          "level": record.level().to_string(),
+          "target": record.target(),
+          "message": record.args().to_string(),
+        })
+      )
+    });
+  }
+
+  builder.init();
+}
+
+// Wrap anything containing keys in this before logging it
+pub(crate) struct Keys<'a, T: Debug>(pub(crate) &'a T);
+
+impl<'a, T: Debug> Debug for Keys<'a, T> {
+  fn fmt(&self, formatter: &mut Formatter) -> std::fmt::Result {
+    if LOGS_KEYS.load(Ordering::Relaxed) {
+      self.0.fmt(formatter)
+    } else {
+      formatter.write_str("<redacted>")
+    }
+  }
+}
@@ -1,50 +1,178 @@
+#[macro_use]
+extern crate nix;
+
 use evdev_rs as evdev;
 use log::*;
 use mio::unix::EventedFd;
 use mio::*;
+use nix::sys::signal::{SigSet, Signal};
+use nix::sys::signalfd::{SfdFlags, SignalFd};
 use std::cell::RefCell;
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, BTreeSet};
 use std::convert::TryInto;
 use std::fs::OpenOptions;
 use std::os::unix::fs::OpenOptionsExt;
 use std::os::unix::io::{AsRawFd, RawFd};
+use std::path::{Path, PathBuf};
 use std::rc::Rc;
+use std::time::{Duration, Instant};
 
 mod remapper;
 use remapper::*;
+mod bench;
 mod config;
 use config::*;
+mod context;
+mod control;
+mod logging;
+use logging::Keys;
+mod seat;
+mod simulate;
+mod socket;
+mod systemd;
 
 trait AsyncWorker: AsRawFd {
   fn step(&mut self, manager: &mut WorkerManager);
+
+  // `wake` gets called once this instant has passed
+  fn deadline(&self) -> Option<Instant> {
+    None
+  }
+
+  fn wake(&mut self, _manager: &mut WorkerManager) {}
+
+  // Called when the worker gets removed from WorkerManager
+  fn stop(&mut self) {}
+
+  // If this returns true after `step`, WorkerManager stops the worker
+  fn is_finished(&self) -> bool {
+    false
+  }
+
+  fn as_keyboard(&mut self) -> Option<&mut KeyPressWorker> {
+    None
+  }
 }
 
 struct WorkerManager {
   poll: mio::Poll,
+  // Configs are leaked on reload, because remappers refer to their rules
+  config: &'static Config,
   workers: BTreeMap<usize, Rc<RefCell<dyn AsyncWorker>>>,
+  running: bool,
+  // Ids for workers which are not tied to a device count down from here.
+  // usize::MAX itself is reserved by mio.
+  next_spawned_id: usize,
+  // Usually the focused application. It selects the profile of every keyboard
+  context: Option<String>,
+  // Counts of keyboards which were disconnected or reconnected by reloads
+  retired_counters: Counters,
+  // Set if systemd expects WATCHDOG=1 periodically
+  watchdog_interval: Option<Duration>,
+  next_watchdog: Instant,
 }
 
 impl WorkerManager {
-  fn new() -> Self {
+  fn new(config: &'static Config) -> Self {
     Self {
       poll: Poll::new().unwrap(),
+      config,
       workers: BTreeMap::new(),
+      running: true,
+      next_spawned_id: usize::MAX - 1,
+      context: None,
+      retired_counters: Counters::default(),
+      watchdog_interval: systemd::watchdog_interval(),
+      next_watchdog: Instant::now(),
     }
   }
 
   fn run(&mut self) {
     let mut events = Events::with_capacity(128);
-    loop {
-      self.poll.poll(&mut events, None).unwrap();
+    while self.running {
+      let timeout = self
+        .next_deadline()
+        .map(|deadline| deadline.saturating_duration_since(Instant::now()));
+      self.poll.poll(&mut events, timeout).unwrap();
 
       for event in events.iter() {
-        if let Some(worker) = self.workers.get_mut(&event.token().0) {
-          Rc::clone(worker).borrow_mut().step(self);
+        let id = event.token().0;
+        if let Some(worker) = self.workers.get_mut(&id) {
+          let worker = Rc::clone(worker);
+          worker.borrow_mut().step(self);
+
+          if worker.borrow().is_finished() {
+            self.stop(id);
+          }
         }
         // The reason why I use Vec<Rc<RefCell<AsyncWorker>>> instead of Vec<Box<AsyncWorker>> is:
         // While doing `worker.step(manager)`, there's a possibility for `step` to remove `worker` itself using `manager`.
         // It took a while to figure out what cryptic messages from borrow checker means...
       }
+
+      self.wake_expired();
+      self.ping_watchdog();
+    }
+
+    systemd::notify("STOPPING=1");
+    let ids: Vec<usize> = self.workers.keys().cloned().collect();
+    for id in ids {
+      self.stop(id);
+    }
+  }
+
+  // Stops every worker and returns from `run` after the current iteration
+  fn exit(&mut self) {
+    self.running = false;
+  }
+
+  fn next_deadline(&self) -> Option<Instant> {
+    let watchdog = self.watchdog_interval.map(|_| self.next_watchdog);
+    self
+      .workers
+      .values()
+      .filter_map(|worker| worker.borrow().deadline())
+      .chain(watchdog)
+      .min()
+  }
+
+  // The loop is alive as long as this gets called
+  fn ping_watchdog(&mut self) {
+    let interval = match self.watchdog_interval {
+      Some(interval) => interval,
+      None => return,
+    };
+
+    let now = Instant::now();
+    if self.next_watchdog <= now {
+      systemd::notify("WATCHDOG=1");
+      self.next_watchdog = now + interval;
+    }
+  }
+
+  fn notify_status(&self) {
+    let mut count = 0;
+    self.for_each_keyboard(|_, _| count += 1);
+    systemd::notify(&format!("STATUS={} keyboard(s) connected", count));
+  }
+
+  fn wake_expired(&mut self) {
+    let now = Instant::now();
+    let expired: Vec<Rc<RefCell<dyn AsyncWorker>>> = self
+      .workers
+      .values()
+      .filter(|worker| {
+        worker
+          .borrow()
+          .deadline()
+          .map(|deadline| deadline <= now)
+          .unwrap_or(false)
+      })
+      .cloned()
+      .collect();
+
+    for worker in expired {
+      worker.borrow_mut().wake(self);
     }
   }
 
@@ -62,17 +190,127 @@ impl WorkerManager {
     self.workers.insert(id, Rc::new(RefCell::new(worker)));
   }
 
+  fn spawn<T: AsyncWorker + 'static>(&mut self, worker: T) {
+    let id = self.next_spawned_id;
+    self.next_spawned_id -= 1;
+    self.start(id, worker);
+  }
+
   fn stop(&mut self, id: usize) {
     match self.workers.remove(&id) {
-      Some(worker) => self
-        .poll
-        .deregister(&EventedFd(&worker.borrow().as_raw_fd()))
-        .unwrap(),
+      Some(worker) => {
+        self
+          .poll
+          .deregister(&EventedFd(&worker.borrow().as_raw_fd()))
+          .unwrap();
+
+        let mut worker = worker.borrow_mut();
+        if let Some(counters) = worker
+          .as_keyboard()
+          .and_then(|keyboard| keyboard.remapper.counters())
+        {
+          self.retired_counters.merge(counters);
+        }
+        worker.stop();
+      }
       None => return,
     }
   }
+
+  // The worker calling this is skipped because it's borrowed already
+  fn for_each_keyboard<F: FnMut(usize, &mut KeyPressWorker)>(&self, mut f: F) {
+    for (id, worker) in self.workers.iter() {
+      if let Ok(mut worker) = worker.try_borrow_mut() {
+        if let Some(keyboard) = worker.as_keyboard() {
+          f(*id, keyboard);
+        }
+      }
+    }
+  }
+
+  fn connect_keyboards(&mut self) {
+    if self
+      .config
+      .devices
+      .iter()
+      .any(|device| !device.profiles.is_empty())
+      && !self.workers.contains_key(&CONTEXT_WORKER_ID)
+    {
+      self.start(CONTEXT_WORKER_ID, context::ContextListenerWorker::new());
+    }
+
+    let ctx = udev::Context::new().unwrap();
+    for keyboard in find_keyboards(&ctx) {
+      let device_id = match keyboard.devnum() {
+        Some(devnum) => devnum,
+        None => continue,
+      };
+
+      if let Some(mut worker) = KeyPressWorker::for_keyboard(&keyboard, self.config) {
+        info!("keyboard found!");
+        worker.switch_profile(self.context.as_deref());
+        self.start(device_id.try_into().unwrap(), worker);
+      }
+    }
+    self.notify_status();
+  }
+
+  // Reconnects every keyboard with the new config. The old config stays if the new one is invalid
+  fn reload(&mut self) -> Result<(), String> {
+    self.config = Box::leak(Box::new(config::load()?));
+
+    let mut keyboard_ids = Vec::new();
+    self.for_each_keyboard(|id, _| keyboard_ids.push(id));
+    for id in keyboard_ids {
+      self.stop(id);
+    }
+
+    info!("Config reloaded");
+    self.connect_keyboards();
+    Ok(())
+  }
+
+  // Counts of every keyboard, including disconnected ones
+  fn counters(&self) -> Counters {
+    let mut result = self.retired_counters.clone();
+    self.for_each_keyboard(|_, keyboard| {
+      if let Some(counters) = keyboard.remapper.counters() {
+        result.merge(counters);
+      }
+    });
+    result
+  }
+
+  fn save_counters(&self) {
+    let path = match self.config.stats.as_ref().and_then(|stats| stats.path.as_ref()) {
+      Some(path) => path,
+      None => return,
+    };
+
+    match self.counters().save(path) {
+      Ok(()) => info!("Stats saved to {}", path.display()),
+      Err(error) => error!("Saving stats to {} failed: {:?}", path.display(), error),
+    }
+  }
+
+  fn set_context(&mut self, context: Option<String>) {
+    if context == self.context {
+      return;
+    }
+
+    debug!("Context changed: {:?}", context);
+    self.for_each_keyboard(|_, keyboard| keyboard.switch_profile(context.as_deref()));
+    self.context = context;
+  }
 }
 
+// udev::Device::devnum never returns these small numbers, so they never conflict with keyboards' ids
+const CONNECTION_WORKER_ID: usize = 0;
+const SIGNAL_WORKER_ID: usize = 1;
+const CONTEXT_WORKER_ID: usize = 2;
+const CONTROL_WORKER_ID: usize = 3;
+const SEAT_WORKER_ID: usize = 4;
+
 struct KeyboardConnectionWorker {
   monitor: udev::MonitorSocket,
 }
@@ -109,14 +347,18 @@ impl AsyncWorker for KeyboardConnectionWorker {
 
       match event.event_type() {
         udev::EventType::Add => {
-          if let Some(worker) = KeyPressWorker::for_keyboard(&connected_device) {
+          if let Some(mut worker) = KeyPressWorker::for_keyboard(&connected_device, manager.config)
+          {
             info!("keyboard {} connected", device_id);
+            worker.switch_profile(manager.context.as_deref());
             manager.start(device_id.try_into().unwrap(), worker);
+            manager.notify_status();
           }
         }
         udev::EventType::Remove => {
           info!("keyboard {} disconnected", device_id);
           manager.stop(device_id.try_into().unwrap());
+          manager.notify_status();
         }
         _ => {}
       }
@@ -124,14 +366,75 @@ impl AsyncWorker for KeyboardConnectionWorker {
   }
 }
 
+// EVIOCGKEY in linux/input.h
+ioctl_read_buf!(eviocgkey, b'E', 0x18, u8);
+
+const WATCHDOG_INTERVAL: Duration = Duration::from_secs(1);
+
+struct SignalWorker {
+  signals: SignalFd,
+}
+
+impl SignalWorker {
+  fn new() -> Self {
+    let mut mask = SigSet::empty();
+    mask.add(Signal::SIGTERM);
+    mask.add(Signal::SIGINT);
+    // Those signals get delivered only through signalfd from now on
+    mask.thread_block().unwrap();
+
+    Self {
+      signals: SignalFd::with_flags(&mask, SfdFlags::SFD_NONBLOCK).unwrap(),
+    }
+  }
+}
+
+impl AsRawFd for SignalWorker {
+  fn as_raw_fd(&self) -> RawFd {
+    self.signals.as_raw_fd()
+  }
+}
+
+impl AsyncWorker for SignalWorker {
+  fn step(&mut self, manager: &mut WorkerManager) {
+    for signal in &mut self.signals {
+      info!("Received signal {}. Releasing all keyboards and exiting...", signal.ssi_signo);
+      manager.exit();
+    }
+  }
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+enum Mode {
+  Remap,
+  // Bypass modes
+  Forward,
+  Ungrab,
+  // Going to grab the keyboard once every key is released.
+  // If a key is held while grabbing, the compositor never sees its release.
+  Regrab,
+}
+
 struct KeyPressWorker {
   actual_keyboard: evdev::Device,
   virtual_keyboard: evdev::UInputDevice,
   remapper: Remapper,
+  config: &'static Config,
+  config_device: &'static Device,
+  devnode: PathBuf,
+  mode: Mode,
+  panic_since: Option<Instant>,
+  next_watchdog: Instant,
+  // The key nasskan is repeating, and when to repeat it next
+  repeating: Option<(EventKey, Instant)>,
 }
 
 impl KeyPressWorker {
-  fn new(path: &std::path::Path, remapper: Remapper) -> Result<Self, nix::errno::Errno> {
+  fn new(
+    path: &Path,
+    config: &'static Config,
+    config_device: &'static Device,
+  ) -> Result<Self, nix::errno::Errno> {
     let file = OpenOptions::new()
       .read(true)
       .custom_flags(nix::fcntl::OFlag::O_NONBLOCK.bits())
@@ -145,25 +448,31 @@ impl KeyPressWorker {
     Ok(Self {
       actual_keyboard,
       virtual_keyboard,
-      remapper,
+      remapper: Remapper::new(config_device, config.stats.is_some()),
+      config,
+      config_device,
+      devnode: path.to_path_buf(),
+      mode: Mode::Remap,
+      panic_since: None,
+      next_watchdog: Instant::now() + WATCHDOG_INTERVAL,
+      repeating: None,
     })
   }
 
-  fn for_keyboard(keyboard: &udev::Device) -> Option<Self> {
+  fn for_keyboard(keyboard: &udev::Device, config: &'static Config) -> Option<Self> {
     let device_file_path = match keyboard.devnode() {
       Some(devnode) => devnode,
       None => return None,
     };
 
-    for config_device in CONFIG.devices.iter() {
+    for config_device in config.devices.iter() {
       let is_connected = config_device
         .if_
         .iter()
         .all(|(name, value)| keyboard.property_value(name).and_then(|x| x.to_str()) == Some(value));
 
       if is_connected {
-        let remapper = Remapper::new(&config_device.then);
-        if let Ok(x) = KeyPressWorker::new(device_file_path, remapper) {
+        if let Ok(x) = KeyPressWorker::new(device_file_path, config, config_device) {
           return Some(x);
         };
 
@@ -176,12 +485,10 @@ impl KeyPressWorker {
 
   fn handle_event(&mut self, input_event: evdev::InputEvent) {
     let key: EventKey = match &input_event.event_code {
-      evdev::enums::EventCode::EV_KEY(ref key) => {
-        trace!("Received an evdev event: {:?}", input_event);
-        key.clone().into()
-      }
+      evdev::enums::EventCode::EV_KEY(ref key) => key.clone().into(),
+      // Like MSC_SCAN, which would reveal keys in a different way
       _ => {
-        trace!("Ignored an evdev event: {:?}", input_event);
+        trace!("Ignored an evdev event which is not a key");
         return;
       }
     };
@@ -190,31 +497,276 @@ impl KeyPressWorker {
       .try_into()
       .expect("an evdev event has invalid value");
     let event = remapper::Event { event_type, key };
+    trace!("Received an evdev event: {:?}", Keys(&event));
+    self.watch_panic_chord();
+
+    if self.is_bypass_chord(&event) {
+      if self.mode == Mode::Remap {
+        info!("Bypass mode started");
+        self.pause();
+      } else {
+        info!("Bypass mode finished");
+        self.resume();
+      }
+      return;
+    }
 
-    debug!("Input: {:?}", event);
+    match self.mode {
+      Mode::Remap => {}
+      Mode::Forward => {
+        self.forward(&input_event);
+        return;
+      }
+      Mode::Ungrab => return,
+      Mode::Regrab => {
+        self.regrab_if_released();
+        return;
+      }
+    }
+
+    debug!("Input: {:?}", Keys(&event));
     let remapped_events = self.remapper.remap(event);
-    debug!("Output: {:?}", remapped_events);
+    debug!("Output: {:?}", Keys(&remapped_events));
+
+    self.send(remapped_events, &input_event.time);
+    self.update_repeating();
+  }
+
+  fn is_paused(&self) -> bool {
+    self.mode != Mode::Remap
+  }
+
+  fn is_bypass_chord(&self, event: &remapper::Event) -> bool {
+    let keys = &self.config.bypass.keys;
+    event.event_type == EventType::Press
+      && keys.contains(&event.key)
+      && keys.iter().all(|key| self.is_physically_pressed(key))
+  }
+
+  fn pause(&mut self) {
+    if self.mode != Mode::Remap {
+      return;
+    }
+
+    let released = self.remapper.sync(&BTreeSet::new(), &BTreeSet::new());
+    self.send(released, &evdev::TimeVal::new(0, 0));
+    self.repeating = None;
+
+    if !self.config.bypass.ungrab {
+      self.mode = Mode::Forward;
+      return;
+    }
+
+    match self.actual_keyboard.grab(evdev::GrabMode::Ungrab) {
+      Ok(()) => self.mode = Mode::Ungrab,
+      Err(error) => {
+        warn!("Ungrabbing a keyboard failed. Forwarding keys instead: {:?}", error);
+        self.mode = Mode::Forward;
+      }
+    }
+  }
+
+  fn resume(&mut self) {
+    match self.mode {
+      Mode::Remap | Mode::Regrab => {}
+      Mode::Forward => {
+        // Keys held now were forwarded without remapping. Their releases would be remapped, so release them here
+        let released: remapper::Frame = self
+          .pressed_keys()
+          .into_iter()
+          .map(|key| remapper::Event {
+            event_type: EventType::Release,
+            key,
+          })
+          .collect();
+        self.send(vec![released], &evdev::TimeVal::new(0, 0));
+        self.mode = Mode::Remap;
+      }
+      Mode::Ungrab => {
+        self.mode = Mode::Regrab;
+        self.regrab_if_released();
+      }
+    }
+  }
+
+  fn regrab_if_released(&mut self) {
+    if !self.pressed_keys().is_empty() {
+      return;
+    }
+
+    match self.actual_keyboard.grab(evdev::GrabMode::Grab) {
+      Ok(()) => self.mode = Mode::Remap,
+      Err(error) => error!("Grabbing a keyboard failed: {:?}", error),
+    }
+  }
+
+  fn switch_profile(&mut self, profile: Option<&str>) {
+    let events = self.remapper.switch_profile(profile);
+    if !events.is_empty() {
+      debug!("Output for profile switch: {:?}", Keys(&events));
+      self.send(events, &evdev::TimeVal::new(0, 0));
+      self.update_repeating();
+    }
+  }
+
+  // `manager` skips this keyboard while it's stepping, so it's changed here
+  fn run_action(&mut self, manager: &mut WorkerManager) {
+    match self.remapper.take_action() {
+      Some(Action::Profile(profile)) => {
+        manager.set_context(profile.clone());
+        self.switch_profile(profile.as_deref());
+      }
+      Some(Action::Pause) => {
+        info!("Paused by a rule");
+        manager.for_each_keyboard(|_, keyboard| keyboard.pause());
+        self.pause();
+      }
+      None => {}
+    }
+  }
+
+  fn update_repeating(&mut self) {
+    let repeat = match &self.config_device.repeat {
+      Some(repeat) => repeat,
+      None => return,
+    };
+
+    let key = self.remapper.repeating_key();
+    if key.as_ref() != self.repeating.as_ref().map(|(repeating, _)| repeating) {
+      self.repeating = key.map(|key| (key, Instant::now() + Duration::from_millis(repeat.delay)));
+    }
+  }
+
+  fn repeat(&mut self) {
+    let (key, _) = match self.repeating.take() {
+      Some(repeating) => repeating,
+      None => return,
+    };
+    let rate = self.config_device.repeat.as_ref().map(|repeat| repeat.rate).unwrap_or(1);
+
+    self.send(
+      vec![vec![remapper::Event {
+        event_type: EventType::Repeat,
+        key: key.clone(),
+      }]],
+      &evdev::TimeVal::new(0, 0),
+    );
+    self.repeating = Some((key, Instant::now() + Duration::from_secs_f64(1.0 / rate as f64)));
+  }
+
+  fn forward(&self, input_event: &evdev::InputEvent) {
+    self.virtual_keyboard.write_event(input_event).unwrap();
+    self
+      .virtual_keyboard
+      .write_event(&evdev::InputEvent::new(
+        &input_event.time,
+        &evdev::enums::EventCode::EV_SYN(evdev::enums::EV_SYN::SYN_REPORT),
+        0,
+      ))
+      .unwrap();
+  }
+
+  // Each frame ends with SYN_REPORT
+  fn send(&self, frames: Vec<remapper::Frame>, time: &evdev::TimeVal) {
+    for frame in frames {
+      for event in frame {
+        self
+          .virtual_keyboard
+          .write_event(&evdev::InputEvent::new(
+            time,
+            &evdev::enums::EventCode::EV_KEY(event.key.into()),
+            event.event_type.into(),
+          ))
+          .unwrap();
+      }
 
-    for remapped_event in remapped_events {
       self
         .virtual_keyboard
         .write_event(&evdev::InputEvent::new(
-          &input_event.time,
-          &evdev::enums::EventCode::EV_KEY(remapped_event.key.into()),
-          remapped_event.event_type.into(),
+          time,
+          &evdev::enums::EventCode::EV_SYN(evdev::enums::EV_SYN::SYN_REPORT),
+          0,
         ))
         .unwrap();
     }
+  }
+
+  // Asks the kernel directly, unlike `is_physically_pressed`
+  fn physically_pressed(&self) -> nix::Result<BTreeSet<EventKey>> {
+    let key_max = evdev::enums::EV_KEY::KEY_MAX as usize;
+    let mut bits = vec![0u8; key_max / 8 + 1];
+    unsafe { eviocgkey(self.as_raw_fd(), &mut bits) }?;
+
+    Ok(
+      (0..=key_max)
+        .filter(|code| bits[code / 8] & (1 << (code % 8)) != 0)
+        .filter_map(|code| evdev::enums::int_to_ev_key(code as u32))
+        .map(EventKey::from)
+        .collect(),
+    )
+  }
+
+  // Releases virtually pressed keys whose physical keys are already released.
+  // That happens only if the remapper has a bug, but a stuck key makes the whole keyboard unusable.
+  fn release_stuck_keys(&mut self) {
+    // Unread events would make the comparison wrong
+    if self.actual_keyboard.has_event_pending() {
+      return;
+    }
+
+    let physically_pressed = match self.physically_pressed() {
+      Ok(keys) => keys,
+      Err(error) => {
+        debug!("EVIOCGKEY failed: {:?}", error);
+        return;
+      }
+    };
+
+    let released = self.remapper.sync(&physically_pressed, &BTreeSet::new());
+    if !released.is_empty() {
+      warn!("Found stuck keys. Releasing them: {:?}", Keys(&released));
+      self.send(released, &evdev::TimeVal::new(0, 0));
+      self.update_repeating();
+    }
+  }
 
+  fn panic_deadline(&self) -> Option<Instant> {
     self
-      .virtual_keyboard
-      .write_event(&evdev::InputEvent {
-        event_type: evdev::enums::EventType::EV_SYN,
-        event_code: evdev::enums::EventCode::EV_SYN(evdev::enums::EV_SYN::SYN_REPORT),
-        value: 0,
-        ..input_event
-      })
-      .unwrap();
+      .panic_since
+      .map(|since| since + Duration::from_millis(self.config.panic.hold))
+  }
+
+  // Uses libevdev's view of the keyboard, so a broken keymap can't get in the way
+  fn is_physically_pressed(&self, key: &EventKey) -> bool {
+    self
+      .actual_keyboard
+      .event_value(&evdev::enums::EventCode::EV_KEY(key.clone().into()))
+      .map(|value| value != 0)
+      .unwrap_or(false)
+  }
+
+  fn pressed_keys(&self) -> BTreeSet<EventKey> {
+    (0..=evdev::enums::EV_KEY::KEY_MAX as u32)
+      .filter_map(evdev::enums::int_to_ev_key)
+      .map(EventKey::from)
+      .filter(|key| self.is_physically_pressed(key))
+      .collect()
+  }
+
+  fn watch_panic_chord(&mut self) {
+    let is_held = !self.config.panic.keys.is_empty()
+      && self
+        .config
+        .panic
+        .keys
+        .iter()
+        .all(|key| self.is_physically_pressed(key));
+
+    match (is_held, self.panic_since) {
+      (true, None) => self.panic_since = Some(Instant::now()),
+      (false, Some(_)) => self.panic_since = None,
+      _ => {}
+    }
   }
 }
 
@@ -233,15 +785,48 @@ impl AsRawFd for KeyPressWorker {
 }
 
 impl AsyncWorker for KeyPressWorker {
-  fn step(&mut self, _: &mut WorkerManager) {
+  fn step(&mut self, manager: &mut WorkerManager) {
     let mut flag = evdev::ReadFlag::NORMAL;
+    let mut newly_pressed = BTreeSet::new();
     loop {
       match self.actual_keyboard.next_event(flag) {
-        Ok((evdev::ReadStatus::Success, event)) => self.handle_event(event),
-        Ok((evdev::ReadStatus::Sync, event)) => {
-          warn!("Nasskan could not keep up with you typing so fast... now trying to recover.");
-          flag = evdev::ReadFlag::SYNC;
+        Ok((evdev::ReadStatus::Success, event)) => {
           self.handle_event(event);
+          self.run_action(manager);
+        }
+        Ok((evdev::ReadStatus::Sync, event)) => {
+          if flag == evdev::ReadFlag::NORMAL {
+            warn!("Nasskan could not keep up with you typing so fast... now trying to recover.");
+            flag = evdev::ReadFlag::SYNC;
+          }
+
+          // Sync events describe the current state, not what you typed. So they don't go to `handle_event`
+          if let evdev::enums::EventCode::EV_KEY(key) = &event.event_code {
+            match self.mode {
+              Mode::Remap if event.value == 1 => {
+                newly_pressed.insert(key.clone().into());
+              }
+              Mode::Forward => self.forward(&event),
+              _ => {}
+            }
+          }
+        }
+        Err(nix::errno::Errno::EAGAIN) if flag == evdev::ReadFlag::SYNC => {
+          flag = evdev::ReadFlag::NORMAL;
+          match self.mode {
+            Mode::Remap => {}
+            Mode::Regrab => {
+              self.regrab_if_released();
+              continue;
+            }
+            _ => continue,
+          }
+
+          let events = self.remapper.sync(&self.pressed_keys(), &newly_pressed);
+          debug!("Output for recovery: {:?}", Keys(&events));
+          self.send(events, &evdev::TimeVal::new(0, 0));
+          self.update_repeating();
+          newly_pressed.clear();
         }
         Err(nix::errno::Errno::EAGAIN) => return,
         Err(nix::errno::Errno::ENODEV) => return,
@@ -252,6 +837,47 @@ impl AsyncWorker for KeyPressWorker {
       };
     }
   }
+
+  fn deadline(&self) -> Option<Instant> {
+    std::iter::once(self.next_watchdog)
+      .chain(self.panic_deadline())
+      .chain(self.repeating.as_ref().map(|(_, next)| *next))
+      .min()
+  }
+
+  fn wake(&mut self, manager: &mut WorkerManager) {
+    let now = Instant::now();
+
+    if self.panic_deadline().map(|deadline| deadline <= now).unwrap_or(false) {
+      warn!("Panic chord was held. Ungrabbing all keyboards and exiting...");
+      manager.exit();
+      return;
+    }
+
+    if self.next_watchdog <= now {
+      self.release_stuck_keys();
+      self.next_watchdog = now + WATCHDOG_INTERVAL;
+    }
+
+    if self.repeating.as_ref().map(|(_, next)| *next <= now).unwrap_or(false) {
+      self.repeat();
+    }
+  }
+
+  fn stop(&mut self) {
+    // Otherwise keys pressed right now would be stuck in the compositor
+    let released = self.remapper.sync(&BTreeSet::new(), &BTreeSet::new());
+    self.send(released, &evdev::TimeVal::new(0, 0));
+
+    // The virtual keyboard gets destroyed when this worker is dropped
+    if let Err(error) = self.actual_keyboard.grab(evdev::GrabMode::Ungrab) {
+      debug!("Ungrabbing a keyboard failed: {:?}", error);
+    }
+  }
+
+  fn as_keyboard(&mut self) -> Option<&mut KeyPressWorker> {
+    Some(self)
+  }
 }
 
 fn find_keyboards(ctx: &udev::Context) -> udev::Devices {
@@ -261,31 +887,90 @@ fn find_keyboards(ctx: &udev::Context) -> udev::Devices {
 }
 
 fn main() {
-  match std::env::var("RUST_LOG") {
-    Ok(_) => env_logger::init(),
-    Err(_) => env_logger::builder()
-      .filter_level(LevelFilter::Trace)
-      .init(),
+  logging::init();
+
+  let args: Vec<String> = std::env::args().skip(1).collect();
+  match args.iter().map(String::as_str).collect::<Vec<&str>>().as_slice() {
+    [] => run_daemon(),
+    ["context", provider] => context::provide(provider),
+    ["dump-config"] => dump_config(config::load()),
+    ["migrate-config"] => migrate_config(&config::system_config_path()),
+    ["migrate-config", path] => migrate_config(Path::new(path)),
+    ["config-schema"] => println!("{}", config::schema()),
+    ["lint"] => lint_config(config::load()),
+    ["lint", path] => lint_config(config::load_from(Path::new(path), None)),
+    ["simulate"] => simulate::run(config::load(), 0),
+    ["simulate", device] => simulate::run(config::load(), device_index(device)),
+    ["bench"] => bench::run(config::load(), 0),
+    ["bench", device] => bench::run(config::load(), device_index(device)),
+    _ => {
+      eprintln!(
+        "Usage: nasskan [context <sway|hyprland> | dump-config | migrate-config [PATH] | config-schema | lint [PATH] | simulate [DEVICE] | bench [DEVICE]]"
+      );
+      std::process::exit(2);
+    }
+  }
+}
+
+fn run_daemon() {
+  let config = config::load().unwrap_or_else(|error| panic!("{}", error));
+  let mut manager = WorkerManager::new(Box::leak(Box::new(config)));
+  manager.start(SIGNAL_WORKER_ID, SignalWorker::new());
+  manager.start(CONTROL_WORKER_ID, control::ControlListenerWorker::new());
+  // Without logind, only the system config is used
+  match seat::SeatWorker::new() {
+    Ok(worker) => manager.start(SEAT_WORKER_ID, worker),
+    Err(error) => warn!("Active user can't be watched: {}", error),
   }
 
-  let mut manager = WorkerManager::new();
   let ctx = udev::Context::new().unwrap();
   let worker = KeyboardConnectionWorker::new(&ctx);
-  // It's safe to use 0 as worker id because udev::Device::devnum never returns Some(0)
-  manager.start(0, worker);
+  manager.start(CONNECTION_WORKER_ID, worker);
   info!("Start watching keyboard connections...");
 
-  for keyboard in find_keyboards(&ctx) {
-    let device_id = match keyboard.devnum() {
-      Some(devnum) => devnum,
-      None => continue,
-    };
+  manager.connect_keyboards();
+  // Every keyboard found so far is grabbed
+  systemd::notify("READY=1");
+  manager.run();
+  manager.save_counters();
+}
 
-    if let Some(worker) = KeyPressWorker::for_keyboard(&keyboard) {
-      info!("keyboard found!");
-      manager.start(device_id.try_into().unwrap(), worker);
-    }
-  }
+// Prints the config as nasskan understands it, in the latest version and with includes and names expanded
+fn dump_config(config: Result<Config, String>) {
+  let config = config.unwrap_or_else(|error| {
+    eprintln!("{}", error);
+    std::process::exit(1);
+  });
+  print!("{}", config::to_yaml(&config));
+}
 
-  manager.run()
+fn migrate_config(path: &Path) {
+  let migrated = config::migrate(path).unwrap_or_else(|error| {
+    eprintln!("{}", error);
+    std::process::exit(1);
+  });
+  print!("{}", migrated);
+}
+
+fn device_index(argument: &str) -> usize {
+  argument.parse().unwrap_or_else(|_| {
+    eprintln!("DEVICE should be an index of devices in the config");
+    std::process::exit(2);
+  })
+}
+
+// Exits with 1 if anything was found, so it can be used in scripts
+fn lint_config(config: Result<Config, String>) {
+  let config = config.unwrap_or_else(|error| {
+    eprintln!("{}", error);
+    std::process::exit(1);
+  });
+
+  let problems = config::lint(&config);
+  for problem in problems.iter() {
+    println!("{}", problem);
+  }
+  if !problems.is_empty() {
+    std::process::exit(1);
+  }
 }
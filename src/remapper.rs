@@ -1,46 +1,218 @@
 use crate::config::*;
 use evdev_rs::enums::EV_KEY;
-use maplit::btreeset;
-use std::cmp::Ordering;
-use std::collections::{BTreeMap, BTreeSet};
+use std::collections::BTreeSet;
 use std::convert::{TryFrom, TryInto};
 use std::ops::Deref;
+use std::time::{Duration, Instant};
+
+mod counters;
+pub(crate) use counters::Counters;
+
+// Older lines are dropped, in case nobody reads the explanation
+const EXPLANATION_LIMIT: usize = 1000;
 
 // Remaps Event to Vec<Event>
 pub(crate) struct Remapper {
+  device: &'static Device,
+  // `device.then`, or rules of the active profile
   keymap: &'static Vec<Rule>,
+  index: &'static KeymapIndex,
   keyboard_state: Vec<KeyState>,
   last_key: EventKey,
+  // When `last_key` was pressed
+  last_pressed_at: Instant,
+  // Repeats from the keyboard are ignored if nasskan generates them by itself
+  forwards_repeats: bool,
+  // None unless `stats` is configured
+  counters: Option<Counters>,
+  // None unless explaining. Why each event was remapped as it was
+  explanation: Option<Vec<String>>,
+  // Action of the rule the last press fired, until it's taken
+  action: Option<&'static Action>,
 }
 
 impl Remapper {
-  pub(crate) fn new(keymap: &'static Vec<Rule>) -> Self {
+  pub(crate) fn new(device: &'static Device, counts: bool) -> Self {
+    let (keymap, index) = device.keymap(None);
     Self {
+      device,
       keymap,
+      index,
       keyboard_state: Vec::new(),
       last_key: EV_KEY::KEY_RESERVED.into(),
+      last_pressed_at: Instant::now(),
+      forwards_repeats: device.repeat.is_none(),
+      counters: if counts { Some(Counters::default()) } else { None },
+      explanation: None,
+      action: None,
     }
   }
 
-  pub(crate) fn remap(&mut self, received: Event) -> BTreeSet<Event> {
+  pub(crate) fn remap(&mut self, received: Event) -> Vec<Frame> {
+    self.explain(|| format!("Received {:?} {}", received.event_type, received.key));
     let old_virtually_pressed = self.virtually_pressed();
 
     self.add_remove_actives(&received);
     self.convert_actives();
 
-    let mut to_be_sent = BTreeSet::new();
-    to_be_sent.extend(self.events_for_diff(&old_virtually_pressed));
-    to_be_sent.extend(self.events_for_tap(&received));
-    to_be_sent.extend(self.events_for_keyrepeats(received.clone()));
+    let mut to_be_sent = self.frames_for_diff(&old_virtually_pressed);
+    to_be_sent.extend(self.frame_for_tap(&received));
+    self.explain_tap(&received);
+    to_be_sent.extend(self.events_for_keyrepeats(received.clone()).map(|event| vec![event]));
+    self.count(&received, &to_be_sent);
+    self.explain(|| format!("Sent {}", describe_events(&to_be_sent)));
+    // The pressed key is always the last one
+    if let (EventType::Press, Some(KeyState::Remapped(rule))) =
+      (received.event_type, self.keyboard_state.last())
+    {
+      self.action = rule.action.as_ref();
+    }
     self.last_key = received.key.clone();
+    if received.event_type == EventType::Press {
+      self.last_pressed_at = Instant::now();
+    }
 
     to_be_sent
   }
 
+  pub(crate) fn take_action(&mut self) -> Option<&'static Action> {
+    self.action.take()
+  }
+
+  pub(crate) fn counters(&self) -> Option<&Counters> {
+    self.counters.as_ref()
+  }
+
+  // Starts or stops recording the explanation. Stopping forgets it
+  pub(crate) fn set_explaining(&mut self, explaining: bool) {
+    if explaining != self.explanation.is_some() {
+      self.explanation = if explaining { Some(Vec::new()) } else { None };
+    }
+  }
+
+  // Lines recorded since the last call
+  pub(crate) fn take_explanation(&mut self) -> Vec<String> {
+    self.explanation.as_mut().map(std::mem::take).unwrap_or_default()
+  }
+
+  fn explain(&mut self, line: impl FnOnce() -> String) {
+    if let Some(explanation) = self.explanation.as_mut() {
+      if explanation.len() == EXPLANATION_LIMIT {
+        explanation.remove(0);
+      }
+      explanation.push(line());
+    }
+  }
+
+  fn explain_tap(&mut self, received: &Event) {
+    if received.event_type != EventType::Release || self.explanation.is_none() {
+      return;
+    }
+
+    if let Some(rule) = self.tap_rule(&received.key) {
+      let tapped = if self.is_tap(received) { "a tap" } else { "not a tap" };
+      let line = format!("{}: the release is {}", self.rule_name(rule), tapped);
+      self.explain(|| line);
+    }
+  }
+
+  // Must be called before `last_key` is updated
+  fn count(&mut self, received: &Event, to_be_sent: &[Frame]) {
+    let tap_rule = match received.event_type {
+      EventType::Release => self.tap_rule(&received.key),
+      _ => None,
+    };
+    let tapped = self.is_tap(received);
+    // The pressed key is always the last one
+    let fired_rule = match (received.event_type, self.keyboard_state.last()) {
+      (EventType::Press, Some(KeyState::Remapped(rule))) => Some(*rule),
+      _ => None,
+    };
+
+    let counters = match &mut self.counters {
+      Some(counters) => counters,
+      None => return,
+    };
+    if received.event_type == EventType::Press {
+      counters.count_physical(&received.key);
+    }
+    for event in to_be_sent.iter().flatten() {
+      if event.event_type == EventType::Press {
+        counters.count_output(&event.key);
+      }
+    }
+    if let Some(rule) = fired_rule {
+      counters.count_fired(rule);
+    }
+    if let Some(rule) = tap_rule {
+      counters.count_tap(rule, tapped);
+    }
+  }
+
+  pub(crate) fn keyboard_state(&self) -> &[KeyState] {
+    &self.keyboard_state
+  }
+
+  // Uses `then` rules if `profile` is None or not defined for this device
+  pub(crate) fn switch_profile(&mut self, profile: Option<&str>) -> Vec<Frame> {
+    let (keymap, index) = self.device.keymap(profile);
+    if std::ptr::eq(keymap, self.keymap) {
+      return Vec::new();
+    }
+
+    let old_virtually_pressed = self.virtually_pressed();
+    self.keymap = keymap;
+    self.index = index;
+    self.convert_actives();
+    self.frames_for_diff(&old_virtually_pressed)
+  }
+
+  // Catches up with key state changes which were not received as events.
+  // Forgets keys which are not in `physically_pressed`, and starts tracking `newly_pressed`.
+  pub(crate) fn sync(
+    &mut self,
+    physically_pressed: &BTreeSet<EventKey>,
+    newly_pressed: &BTreeSet<EventKey>,
+  ) -> Vec<Frame> {
+    let old_virtually_pressed = self.virtually_pressed();
+    let old_keyboard_state = self.keyboard_state.clone();
+
+    self
+      .keyboard_state
+      .retain(|key_state| physically_pressed.contains(&key_state.original_key()));
+    for key in newly_pressed.intersection(physically_pressed) {
+      if old_keyboard_state
+        .iter()
+        .all(|key_state| &key_state.original_key() != key)
+      {
+        self.keyboard_state.push(KeyState::Passthru(key.clone()));
+      }
+    }
+
+    if self.keyboard_state == old_keyboard_state {
+      return Vec::new();
+    }
+
+    // Keys changed behind our back must not make a tap
+    self.last_key = EV_KEY::KEY_RESERVED.into();
+    self.convert_actives();
+    self.frames_for_diff(&old_virtually_pressed)
+  }
+
   fn add_remove_actives(&mut self, received: &Event) {
     let empty = BTreeSet::new();
-    let modifier_map = self.modifier_map();
+    let modifier_map = self.index.modifier_map();
     let remapped_modifier = modifier_map.get(&received.key);
+    // Rules holding the released key like a modifier end too
+    let released_keys: Vec<EventKey> = std::iter::once(received.key.clone())
+      .chain(
+        self
+          .keyboard_state
+          .iter()
+          .filter(|key_state| key_state.original_key() == received.key)
+          .map(KeyState::remapped_key),
+      )
+      .collect();
 
     match received.event_type {
       EventType::Press => {
@@ -59,9 +231,20 @@ impl Remapper {
                   .with
                   .as_ref()
                   .unwrap_or(&empty)
-                  .contains(remapped_modifier)
+                  .iter()
+                  .any(|modifier| modifier.matches(remapped_modifier))
               })
               .unwrap_or(true)
+            && !rule
+              .from
+              .with
+              .as_ref()
+              .unwrap_or(&empty)
+              .iter()
+              .any(|modifier| match modifier {
+                Modifier::Key(key) => released_keys.contains(key),
+                _ => false,
+              })
         }
       }),
       EventType::Repeat => {}
@@ -81,15 +264,37 @@ impl Remapper {
       *key_state = KeyState::Passthru(EV_KEY::KEY_RESERVED.into())
     }
 
-    for config_rule in self.keymap.iter() {
+    // Only rules for pressed keys, in the order KeymapIndex decided.
+    // The same ones are explained, or it'd be every rule every time.
+    let keymap: &'static Vec<Rule> = self.keymap;
+    let mut explanation = Vec::new();
+    for config_rule in self.index.candidates(&original_keys).into_iter().map(|i| &keymap[i]) {
       for (i, original_key) in original_keys.iter().enumerate() {
-        if let KeyState::Remapped(_) = self.keyboard_state[i] {
+        if original_key != &config_rule.from.key {
+          continue;
+        }
+
+        if let KeyState::Remapped(rule) = self.keyboard_state[i] {
+          if self.explanation.is_some() {
+            explanation.push(format!(
+              "{}: {} is already remapped by {}",
+              self.rule_name(config_rule),
+              original_key,
+              self.rule_name(rule)
+            ));
+          }
           continue;
         }
 
-        if self.is_active(config_rule, &original_key) {
-          std::mem::replace(&mut self.keyboard_state[i], KeyState::Remapped(config_rule));
-          break;
+        match self.unmet_condition(config_rule, &original_keys) {
+          None => {
+            std::mem::replace(&mut self.keyboard_state[i], KeyState::Remapped(config_rule));
+            break;
+          }
+          Some(reason) if self.explanation.is_some() => {
+            explanation.push(format!("{}: {}", self.rule_name(config_rule), reason));
+          }
+          Some(_) => {}
         }
       }
     }
@@ -99,34 +304,99 @@ impl Remapper {
         *key_state = KeyState::Passthru(original_keys[i].clone());
       }
     }
-  }
-
-  fn events_for_diff(&self, old_virtually_pressed: &BTreeSet<EventKey>) -> BTreeSet<Event> {
-    let mut result = BTreeSet::new();
 
-    for press in self.virtually_pressed().difference(old_virtually_pressed) {
-      result.insert(Event {
-        event_type: EventType::Press,
-        key: press.clone(),
-      });
+    if self.explanation.is_some() {
+      for key_state in self.keyboard_state.iter() {
+        explanation.push(match key_state {
+          KeyState::Passthru(key) => format!("{} is passed through", key),
+          KeyState::Remapped(rule) => {
+            format!("{} is remapped by {}", rule.from.key, self.rule_name(rule))
+          }
+        });
+      }
+    }
+    for line in explanation {
+      self.explain(|| line);
     }
+  }
 
-    for release in old_virtually_pressed.difference(&self.virtually_pressed()) {
-      result.insert(Event {
-        event_type: EventType::Release,
-        key: release.clone(),
-      });
+  // Its position in the keymap and what it does, e.g. "rule 2 (SHIFT+A -> C)"
+  fn rule_name(&self, rule: &Rule) -> String {
+    let position = self.keymap.iter().position(|other| std::ptr::eq(other, rule));
+    match position {
+      Some(position) => format!("rule {} ({})", position + 1, counters::describe(rule)),
+      None => format!("rule ({})", counters::describe(rule)),
     }
+  }
 
-    result
+  // Key releases, then modifier changes, then key presses, so no key is typed with stale modifiers.
+  // Modifier changes get their own frame if the device splits them.
+  fn frames_for_diff(&self, old_virtually_pressed: &BTreeSet<EventKey>) -> Vec<Frame> {
+    let virtually_pressed = self.virtually_pressed();
+    // Rules to RESERVED make keys press nothing
+    let reserved: EventKey = EV_KEY::KEY_RESERVED.into();
+    let is_modifier = |key: &&EventKey| Modifier::try_from(*key).is_ok();
+    let events = |event_type: EventType, keys: Vec<&EventKey>| -> Frame {
+      keys
+        .into_iter()
+        .map(|key| Event {
+          event_type,
+          key: key.clone(),
+        })
+        .collect()
+    };
+
+    let (released_modifiers, released_keys): (Vec<&EventKey>, Vec<&EventKey>) = old_virtually_pressed
+      .difference(&virtually_pressed)
+      .filter(|key| **key != reserved)
+      .partition(is_modifier);
+    let (pressed_modifiers, pressed_keys): (Vec<&EventKey>, Vec<&EventKey>) = virtually_pressed
+      .difference(old_virtually_pressed)
+      .filter(|key| **key != reserved)
+      .partition(is_modifier);
+
+    let mut modifier_changes = events(EventType::Release, released_modifiers);
+    modifier_changes.extend(events(EventType::Press, pressed_modifiers));
+    let frames = vec![
+      events(EventType::Release, released_keys),
+      modifier_changes,
+      events(EventType::Press, pressed_keys),
+    ];
+
+    let frames = if self.device.split_modifiers {
+      frames
+    } else {
+      vec![frames.concat()]
+    };
+    frames.into_iter().filter(|frame| !frame.is_empty()).collect()
+  }
+
+  // The key which should get repeated while it's held.
+  // Modifiers pressed after it don't stop it, like they don't in other environments.
+  pub(crate) fn repeating_key(&self) -> Option<EventKey> {
+    let key_state = self.keyboard_state.iter().rev().find(|key_state| {
+      let modifier: Option<Modifier> = key_state.remapped_key().try_into().ok();
+      modifier.is_none()
+    })?;
+
+    match key_state {
+      KeyState::Passthru(key) => Some(key.clone()),
+      KeyState::Remapped(rule) if *rule.to.key == EV_KEY::KEY_RESERVED => None,
+      KeyState::Remapped(rule) if rule.repeat.unwrap_or(true) => Some(rule.to.key.clone()),
+      KeyState::Remapped(_) => None,
+    }
   }
 
   fn events_for_keyrepeats(&self, received: Event) -> Option<Event> {
-    if received.event_type != EventType::Repeat {
+    if received.event_type != EventType::Repeat || !self.forwards_repeats {
       return None;
     }
 
     for rule in self.active_rules() {
+      // Rules to RESERVED press nothing, so there's nothing to repeat either
+      if received.key == rule.from.key && *rule.to.key == EV_KEY::KEY_RESERVED {
+        return None;
+      }
       if received.key == rule.from.key {
         return Some(Event {
           event_type: EventType::Repeat,
@@ -138,78 +408,115 @@ impl Remapper {
     Some(received)
   }
 
-  fn events_for_tap(&self, received: &Event) -> BTreeSet<Event> {
-    if received.event_type != EventType::Release {
-      return BTreeSet::new();
+  fn frame_for_tap(&self, received: &Event) -> Option<Frame> {
+    if received.event_type != EventType::Release || !self.is_tap(received) {
+      return None;
     }
 
-    if self.last_key != received.key {
-      return BTreeSet::new();
-    }
+    let tap = self.tap_rule(&received.key)?.tap.as_ref()?;
+    Some(vec![
+      Event {
+        event_type: EventType::Press,
+        key: tap.key.clone(),
+      },
+      Event {
+        event_type: EventType::Release,
+        key: tap.key.clone(),
+      },
+    ])
+  }
 
-    for rule in self.keymap.iter() {
-      if let Some(tap) = &rule.tap {
-        if received.key == rule.from.key {
-          return btreeset![
-            Event {
-              event_type: EventType::Press,
-              key: tap.key.clone()
-            },
-            Event {
-              event_type: EventType::Release,
-              key: tap.key.clone()
-            }
-          ];
-        }
-      }
-    }
+  // No other key was pressed while `received` was held, and it wasn't held too long
+  fn is_tap(&self, received: &Event) -> bool {
+    self.last_key == received.key
+      && self
+        .device
+        .tap_timeout
+        .map(|timeout| self.last_pressed_at.elapsed() <= Duration::from_millis(timeout))
+        .unwrap_or(true)
+  }
 
-    BTreeSet::new()
+  fn tap_rule(&self, key: &EventKey) -> Option<&'static Rule> {
+    let keymap: &'static Vec<Rule> = self.keymap;
+    self.index.tap(key).map(|i| &keymap[i])
   }
 
-  fn is_active(&self, rule: &'static Rule, pressed: &EventKey) -> bool {
+  // Why `rule` can't remap its from.key now, or None if it can.
+  // `original_keys` are needed because `keyboard_state` is being converted.
+  fn unmet_condition(&self, rule: &'static Rule, original_keys: &[EventKey]) -> Option<String> {
     let remapped_modifiers: BTreeSet<Modifier> = self
       .keyboard_state
       .iter()
       .map(|key_state| key_state.remapped_key())
       .filter_map(|key| key.try_into().ok())
       .collect();
-
-    pressed == &rule.from.key
-      && rule
-        .from
-        .with
-        .as_ref()
-        .map(|config_modifiers| remapped_modifiers.is_superset(&config_modifiers))
-        .unwrap_or(true)
-      && rule
-        .from
-        .without
-        .as_ref()
-        .map(|config_modifiers| remapped_modifiers.is_disjoint(&config_modifiers))
-        .unwrap_or(true)
+    // Keys in `with` or `without` may be either original or remapped ones
+    let held_keys: BTreeSet<EventKey> = self
+      .keyboard_state
+      .iter()
+      .map(|key_state| key_state.remapped_key())
+      .chain(original_keys.iter().cloned())
+      .collect();
+    let is_held = |config_modifier: &Modifier| match config_modifier {
+      Modifier::Key(key) => held_keys.contains(key),
+      _ => remapped_modifiers
+        .iter()
+        .any(|modifier| config_modifier.matches(modifier)),
+    };
+
+    if let Some(missing) = rule.from.with.iter().flatten().find(|modifier| !is_held(modifier)) {
+      return Some(format!("with {} is not held", missing));
+    }
+    if let Some(present) = rule.from.without.iter().flatten().find(|modifier| is_held(modifier)) {
+      return Some(format!("without {} is held", present));
+    }
+    None
   }
 
+  // Remapped keys, with modifiers decided by active rules:
+  // - Rules pressing modifiers keep their `to.with` pressed and `from.with` released while held.
+  // - Among other keys, only the last pressed one decides, because that's the one being typed.
+  //   Its `to.with` wins over the others. Modifiers pressed after it are left alone.
+  //   One set of modifiers can't suit every held key, and only the last one is pressed or repeated.
   fn virtually_pressed(&self) -> BTreeSet<EventKey> {
-    let empty = BTreeSet::new();
     let mut result: BTreeSet<EventKey> = self
       .keyboard_state
       .iter()
-      .map(|key_state| key_state.remapped_key())
+      .map(KeyState::remapped_key)
       .collect();
 
-    if let Some(last_key_state) = self.keyboard_state.last() {
-      if let KeyState::Remapped(last_rule) = last_key_state {
-        for modifier in last_rule.from.with.as_ref().unwrap_or(&empty).iter() {
-          result.remove(&modifier.into());
-        }
-
-        result.insert(last_rule.to.key.clone());
-
-        for modifier in last_rule.to.with.as_ref().unwrap_or(&empty).iter() {
-          result.insert(modifier.into());
+    let is_modifier = |key_state: &KeyState| Modifier::try_from(key_state.remapped_key()).is_ok();
+    let typed = self
+      .keyboard_state
+      .iter()
+      .rposition(|key_state| !is_modifier(key_state));
+    // The typed key goes last, so its `to.with` wins
+    let (typed_rule, modifier_rules): (Vec<_>, Vec<_>) = self
+      .keyboard_state
+      .iter()
+      .enumerate()
+      .filter_map(|(i, key_state)| match key_state {
+        KeyState::Remapped(rule) if is_modifier(key_state) || Some(i) == typed => Some((i, *rule)),
+        _ => None,
+      })
+      .partition(|(i, _): &(usize, &Rule)| Some(*i) == typed);
+
+    for (i, rule) in modifier_rules.into_iter().chain(typed_rule) {
+      let pressed_later: BTreeSet<EventKey> = self.keyboard_state[i + 1..]
+        .iter()
+        .map(KeyState::remapped_key)
+        .collect();
+
+      for modifier in rule.from.with.iter().flatten().flat_map(Modifier::sides) {
+        let key: EventKey = modifier.into();
+        if !pressed_later.contains(&key) {
+          result.remove(&key);
         }
       }
+      result.insert(rule.to.key.clone());
+      for modifier in rule.to.with.iter().flatten() {
+        result.insert(modifier.into());
+      }
     }
 
     result
@@ -224,22 +531,10 @@ impl Remapper {
         KeyState::Remapped(rule) => Some(*rule),
       })
   }
-
-  fn modifier_map(&self) -> BTreeMap<EventKey, Modifier> {
-    let mut result = BTreeMap::new();
-
-    for rule in self.keymap.iter() {
-      if let Some(modifier) = (&rule.to.key).try_into().ok() {
-        result.insert(rule.from.key.clone(), modifier);
-      }
-    }
-
-    result
-  }
 }
 
 #[derive(Debug, Clone, Eq, PartialEq)]
-enum KeyState {
+pub(crate) enum KeyState {
   Passthru(EventKey),
   Remapped(&'static Rule),
 }
@@ -260,33 +555,34 @@ impl KeyState {
   }
 }
 
+// Frames are separated by "|"
+fn describe_events(frames: &[Frame]) -> String {
+  if frames.is_empty() {
+    return "nothing".to_string();
+  }
+
+  let frames: Vec<String> = frames
+    .iter()
+    .map(|frame| {
+      let events: Vec<String> = frame
+        .iter()
+        .map(|event| format!("{:?} {}", event.event_type, event.key))
+        .collect();
+      events.join(", ")
+    })
+    .collect();
+  frames.join(" | ")
+}
+
+// Events between two SYN_REPORTs, in the order they're sent
+pub(crate) type Frame = Vec<Event>;
+
 #[derive(Debug, Clone, Eq, PartialEq)]
 pub(crate) struct Event {
   pub(crate) event_type: EventType,
   pub(crate) key: EventKey,
 }
 
-impl Ord for Event {
-  // events for modifier keys are smaller
-  // cuz those events need to get sent first
-  fn cmp(&self, other: &Self) -> Ordering {
-    let modifier1: Option<Modifier> = (&self.key).try_into().ok();
-    let modifier2: Option<Modifier> = (&other.key).try_into().ok();
-
-    match (modifier1, modifier2) {
-      (Some(_), None) => Ordering::Less,
-      (None, Some(_)) => Ordering::Greater,
-      _ => (self.key.clone(), self.event_type).cmp(&(other.key.clone(), other.event_type)),
-    }
-  }
-}
-
-impl PartialOrd for Event {
-  fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
-    Some(self.cmp(other))
-  }
-}
-
 #[derive(Debug, Clone, Copy, Eq, PartialEq, Ord, PartialOrd)]
 pub(crate) enum EventType {
   Press,
@@ -360,6 +656,290 @@ impl Into<EventKey> for &Modifier {
       Modifier::RIGHTALT => EV_KEY::KEY_RIGHTALT.into(),
       Modifier::LEFTMETA => EV_KEY::KEY_LEFTMETA.into(),
       Modifier::RIGHTMETA => EV_KEY::KEY_RIGHTMETA.into(),
+      // These are resolved to a side when the config is loaded
+      Modifier::Key(key) => key.clone(),
+      Modifier::SHIFT | Modifier::CTRL | Modifier::ALT | Modifier::META => {
+        unreachable!("{:?} has no key", self)
+      }
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use EventType::*;
+
+  const ROLLS: &str = "
+if: {}
+then:
+  - from: { key: LEFTSHIFT }
+    to: { key: LEFTSHIFT }
+  - from: { key: RIGHTSHIFT }
+    to: { key: RIGHTSHIFT }
+  - from: { key: A, with: [SHIFT] }
+    to: { key: B }
+  - from: { key: C }
+    to: { key: D, with: [LEFTCTRL] }
+";
+
+  fn remapper(device: &str) -> Remapper {
+    let mut device: Device = serde_yaml::from_str(device).unwrap();
+    let profiles = device
+      .profiles
+      .iter()
+      .map(|(name, rules)| (Some(name.clone()), KeymapIndex::new(rules)));
+    device.indexes = std::iter::once((None, KeymapIndex::new(&device.then)))
+      .chain(profiles)
+      .collect();
+    Remapper::new(Box::leak(Box::new(device)), false)
+  }
+
+  fn event(event_type: EventType, key: &str) -> Event {
+    Event {
+      event_type,
+      key: key.parse().unwrap(),
     }
   }
+
+  fn key(name: &str) -> EventKey {
+    name.parse().unwrap()
+  }
+
+  fn frame(events: &[(EventType, &str)]) -> Frame {
+    events
+      .iter()
+      .map(|(event_type, key)| event(*event_type, key))
+      .collect()
+  }
+
+  #[test]
+  fn rolls_from_shifted_key_into_key_with_ctrl() {
+    let mut remapper = remapper(ROLLS);
+    remapper.remap(event(Press, "LEFTSHIFT"));
+
+    assert_eq!(
+      remapper.remap(event(Press, "A")),
+      vec![frame(&[(Release, "LEFTSHIFT"), (Press, "B")])]
+    );
+    // C doesn't need SHIFT released, and LEFTSHIFT is still held
+    assert_eq!(
+      remapper.remap(event(Press, "C")),
+      vec![frame(&[(Press, "LEFTCTRL"), (Press, "LEFTSHIFT"), (Press, "D")])]
+    );
+    assert_eq!(remapper.remap(event(Release, "A")), vec![frame(&[(Release, "B")])]);
+  }
+
+  #[test]
+  fn rolls_from_key_with_ctrl_into_shifted_key() {
+    let mut remapper = remapper(ROLLS);
+    assert_eq!(
+      remapper.remap(event(Press, "C")),
+      vec![frame(&[(Press, "LEFTCTRL"), (Press, "D")])]
+    );
+    assert_eq!(
+      remapper.remap(event(Press, "LEFTSHIFT")),
+      vec![frame(&[(Press, "LEFTSHIFT")])]
+    );
+    // B is typed now, so LEFTCTRL for D goes away too
+    assert_eq!(
+      remapper.remap(event(Press, "A")),
+      vec![frame(&[(Release, "LEFTCTRL"), (Release, "LEFTSHIFT"), (Press, "B")])]
+    );
+    assert_eq!(remapper.remap(event(Release, "C")), vec![frame(&[(Release, "D")])]);
+    assert_eq!(
+      remapper.remap(event(Release, "A")),
+      vec![frame(&[(Release, "B"), (Press, "LEFTSHIFT")])]
+    );
+  }
+
+  #[test]
+  fn keeps_modifiers_pressed_after_typed_key() {
+    let mut remapper = remapper(ROLLS);
+    remapper.remap(event(Press, "LEFTSHIFT"));
+    remapper.remap(event(Press, "A"));
+
+    // SHIFT is released for B, but not RIGHTSHIFT pressed after it
+    assert_eq!(
+      remapper.remap(event(Press, "RIGHTSHIFT")),
+      vec![frame(&[(Press, "RIGHTSHIFT")])]
+    );
+  }
+
+  const CHORDS: &str = "
+if: {}
+then:
+  - { from: { key: LEFTCTRL }, to: { key: LEFTCTRL } }
+  - { from: { key: A, with: [LEFTCTRL] }, to: { key: X, with: [LEFTSHIFT] } }
+  - { from: { key: C, with: [LEFTCTRL] }, to: { key: D, with: [LEFTALT] } }
+";
+
+  #[test]
+  fn rolls_between_chords() {
+    let mut remapper = remapper(CHORDS);
+    remapper.remap(event(Press, "LEFTCTRL"));
+
+    assert_eq!(
+      remapper.remap(event(Press, "A")),
+      vec![frame(&[(Release, "LEFTCTRL"), (Press, "LEFTSHIFT"), (Press, "X")])]
+    );
+    assert_eq!(
+      remapper.remap(event(Press, "C")),
+      vec![frame(&[(Release, "LEFTSHIFT"), (Press, "LEFTALT"), (Press, "D")])]
+    );
+    assert_eq!(remapper.remap(event(Release, "A")), vec![frame(&[(Release, "X")])]);
+    assert_eq!(
+      remapper.remap(event(Release, "C")),
+      vec![frame(&[(Release, "D"), (Release, "LEFTALT"), (Press, "LEFTCTRL")])]
+    );
+  }
+
+  #[test]
+  fn rolls_between_chords_in_reverse() {
+    let mut remapper = remapper(CHORDS);
+    remapper.remap(event(Press, "LEFTCTRL"));
+
+    assert_eq!(
+      remapper.remap(event(Press, "C")),
+      vec![frame(&[(Release, "LEFTCTRL"), (Press, "LEFTALT"), (Press, "D")])]
+    );
+    assert_eq!(
+      remapper.remap(event(Press, "A")),
+      vec![frame(&[(Release, "LEFTALT"), (Press, "LEFTSHIFT"), (Press, "X")])]
+    );
+    assert_eq!(remapper.remap(event(Release, "C")), vec![frame(&[(Release, "D")])]);
+    assert_eq!(
+      remapper.remap(event(Release, "A")),
+      vec![frame(&[(Release, "X"), (Release, "LEFTSHIFT"), (Press, "LEFTCTRL")])]
+    );
+  }
+
+  #[test]
+  fn goes_back_to_modifiers_of_held_chord() {
+    let mut remapper = remapper(CHORDS);
+    remapper.remap(event(Press, "LEFTCTRL"));
+    remapper.remap(event(Press, "A"));
+    remapper.remap(event(Press, "C"));
+
+    // X is the key being typed again, and nasskan's own repeat repeats it
+    assert_eq!(
+      remapper.remap(event(Release, "C")),
+      vec![frame(&[(Release, "D"), (Release, "LEFTALT"), (Press, "LEFTSHIFT")])]
+    );
+    assert_eq!(remapper.repeating_key(), Some("X".parse().unwrap()));
+  }
+
+  #[test]
+  fn takes_actions_of_pressed_rules() {
+    let mut remapper = remapper(
+      "
+if: {}
+then:
+  - { from: { key: F1 }, action: { profile: game } }
+  - { from: { key: F2 }, action: pause }
+",
+    );
+    assert_eq!(remapper.remap(event(Press, "F1")), Vec::<Frame>::new());
+    assert_eq!(remapper.take_action(), Some(&Action::Profile(Some("game".to_string()))));
+    assert_eq!(remapper.take_action(), None);
+
+    remapper.remap(event(Release, "F1"));
+    assert_eq!(remapper.take_action(), None);
+    remapper.remap(event(Press, "F2"));
+    assert_eq!(remapper.take_action(), Some(&Action::Pause));
+  }
+
+  fn switching_profile(split_modifiers: bool) -> Vec<Frame> {
+    let mut remapper = remapper(&format!(
+      "
+if: {{}}
+split_modifiers: {}
+then:
+  - {{ from: {{ key: J }}, to: {{ key: D, with: [LEFTCTRL] }} }}
+profiles:
+  p:
+    - {{ from: {{ key: J }}, to: {{ key: E, with: [LEFTALT] }} }}
+",
+      split_modifiers
+    ));
+    remapper.remap(event(Press, "J"));
+    remapper.switch_profile(Some("p"))
+  }
+
+  #[test]
+  fn sends_releases_then_modifiers_then_presses() {
+    assert_eq!(
+      switching_profile(false),
+      vec![frame(&[
+        (Release, "D"),
+        (Release, "LEFTCTRL"),
+        (Press, "LEFTALT"),
+        (Press, "E")
+      ])]
+    );
+  }
+
+  #[test]
+  fn splits_modifier_changes_into_their_own_frame() {
+    assert_eq!(
+      switching_profile(true),
+      vec![
+        frame(&[(Release, "D")]),
+        frame(&[(Release, "LEFTCTRL"), (Press, "LEFTALT")]),
+        frame(&[(Press, "E")]),
+      ]
+    );
+  }
+
+  #[test]
+  fn applies_modifier_rules_written_after_rules_using_them() {
+    let mut remapper = remapper(
+      "
+if: {}
+then:
+  - { from: { key: J, with: [LEFTCTRL] }, to: { key: DOWN } }
+  - { from: { key: CAPSLOCK }, to: { key: LEFTCTRL } }
+",
+    );
+    assert_eq!(
+      remapper.remap(event(Press, "CAPSLOCK")),
+      vec![frame(&[(Press, "LEFTCTRL")])]
+    );
+    assert_eq!(
+      remapper.remap(event(Press, "J")),
+      vec![frame(&[(Release, "LEFTCTRL"), (Press, "DOWN")])]
+    );
+
+    // Both rules are tried again at once here
+    assert_eq!(remapper.switch_profile(None), Vec::<Frame>::new());
+  }
+
+  #[test]
+  fn releases_keys_released_behind_its_back() {
+    let mut remapper = remapper(ROLLS);
+    remapper.remap(event(Press, "C"));
+    let c: BTreeSet<EventKey> = std::iter::once(key("C")).collect();
+
+    // Nothing changed
+    assert_eq!(remapper.sync(&c, &BTreeSet::new()), Vec::<Frame>::new());
+    // C was released while events were dropped, or its release never arrived
+    assert_eq!(
+      remapper.sync(&BTreeSet::new(), &BTreeSet::new()),
+      vec![frame(&[(Release, "D"), (Release, "LEFTCTRL")])]
+    );
+    assert!(remapper.keyboard_state().is_empty());
+  }
+
+  #[test]
+  fn remaps_keys_pressed_behind_its_back() {
+    let mut remapper = remapper(ROLLS);
+    remapper.remap(event(Press, "LEFTSHIFT"));
+    let pressed: BTreeSet<EventKey> = vec![key("LEFTSHIFT"), key("A")].into_iter().collect();
+    let a: BTreeSet<EventKey> = std::iter::once(key("A")).collect();
+
+    assert_eq!(
+      remapper.sync(&pressed, &a),
+      vec![frame(&[(Release, "LEFTSHIFT"), (Press, "B")])]
+    );
+  }
 }
@@ -0,0 +1,154 @@
+use crate::config::{EventKey, Modifier, Rule};
+use serde_json::json;
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::Path;
+
+// Aggregated counts for tuning keymaps.
+// Never keep the order of keys here, or this becomes a keylogger.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Counters {
+  // Presses of physical keys
+  physical: BTreeMap<EventKey, u64>,
+  // Presses sent to virtual keyboards
+  output: BTreeMap<EventKey, u64>,
+  // Keyed by addresses of rules, since rules don't have names
+  rules: BTreeMap<usize, RuleCounter>,
+}
+
+#[derive(Debug, Clone)]
+struct RuleCounter {
+  rule: &'static Rule,
+  fired: u64,
+  taps: u64,
+  holds: u64,
+}
+
+impl Counters {
+  pub(crate) fn count_physical(&mut self, key: &EventKey) {
+    *self.physical.entry(key.clone()).or_insert(0) += 1;
+  }
+
+  pub(crate) fn count_output(&mut self, key: &EventKey) {
+    *self.output.entry(key.clone()).or_insert(0) += 1;
+  }
+
+  pub(crate) fn count_fired(&mut self, rule: &'static Rule) {
+    self.rule_counter(rule).fired += 1;
+  }
+
+  pub(crate) fn count_tap(&mut self, rule: &'static Rule, tapped: bool) {
+    let counter = self.rule_counter(rule);
+    if tapped {
+      counter.taps += 1;
+    } else {
+      counter.holds += 1;
+    }
+  }
+
+  fn rule_counter(&mut self, rule: &'static Rule) -> &mut RuleCounter {
+    self
+      .rules
+      .entry(rule as *const Rule as usize)
+      .or_insert(RuleCounter {
+        rule,
+        fired: 0,
+        taps: 0,
+        holds: 0,
+      })
+  }
+
+  pub(crate) fn merge(&mut self, other: &Counters) {
+    for (key, count) in other.physical.iter() {
+      *self.physical.entry(key.clone()).or_insert(0) += count;
+    }
+    for (key, count) in other.output.iter() {
+      *self.output.entry(key.clone()).or_insert(0) += count;
+    }
+    for (address, other_counter) in other.rules.iter() {
+      let counter = self
+        .rules
+        .entry(*address)
+        .or_insert_with(|| RuleCounter {
+          fired: 0,
+          taps: 0,
+          holds: 0,
+          ..other_counter.clone()
+        });
+      counter.fired += other_counter.fired;
+      counter.taps += other_counter.taps;
+      counter.holds += other_counter.holds;
+    }
+  }
+
+  pub(crate) fn to_json(&self) -> serde_json::Value {
+    let names = |counts: &BTreeMap<EventKey, u64>| -> BTreeMap<String, u64> {
+      counts
+        .iter()
+        .map(|(key, count)| (key.to_string(), *count))
+        .collect()
+    };
+    let rules: Vec<serde_json::Value> = self
+      .rules
+      .values()
+      .map(|counter| {
+        json!({
+          "rule": describe(counter.rule),
+          "fired": counter.fired,
+          "taps": counter.taps,
+          "holds": counter.holds,
+        })
+      })
+      .collect();
+
+    json!({
+      "physical": names(&self.physical),
+      "output": names(&self.output),
+      "rules": rules,
+    })
+  }
+
+  pub(crate) fn to_csv(&self) -> String {
+    let mut result = String::from("category,name,count\n");
+    for (key, count) in self.physical.iter() {
+      result += &format!("physical,{},{}\n", key, count);
+    }
+    for (key, count) in self.output.iter() {
+      result += &format!("output,{},{}\n", key, count);
+    }
+    for counter in self.rules.values() {
+      let rule = describe(counter.rule);
+      result += &format!("fired,{},{}\n", rule, counter.fired);
+      result += &format!("taps,{},{}\n", rule, counter.taps);
+      result += &format!("holds,{},{}\n", rule, counter.holds);
+    }
+    result
+  }
+
+  // CSV if the extension is csv, JSON otherwise
+  pub(crate) fn save(&self, path: &Path) -> std::io::Result<()> {
+    let content = match path.extension().and_then(|extension| extension.to_str()) {
+      Some("csv") => self.to_csv(),
+      _ => serde_json::to_string_pretty(&self.to_json()).unwrap(),
+    };
+    std::fs::write(path, content)
+  }
+}
+
+// Like "LEFTSHIFT+1 -> BACKSLASH". Never contains commas, so it's safe in CSV
+pub(super) fn describe(rule: &Rule) -> String {
+  let side = |key: &EventKey, modifiers: &Option<BTreeSet<Modifier>>| {
+    let mut names: Vec<String> = modifiers
+      .iter()
+      .flatten()
+      .map(|modifier| modifier.to_string())
+      .collect();
+    names.push(key.to_string());
+    names.join("+")
+  };
+
+  format!(
+    "{} -> {}",
+    side(&rule.from.key, &rule.from.with),
+    side(&rule.to.key, &rule.to.with)
+  )
+}
@@ -0,0 +1,96 @@
+use crate::{AsyncWorker, WorkerManager};
+use log::*;
+use nix::libc;
+use std::ffi::{CStr, CString};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::path::{Path, PathBuf};
+
+// logind writes the state of each seat here
+const SEATS_DIR: &str = "/run/systemd/seats";
+const SEAT: &str = "seat0";
+
+// The user of the active session, who is the one in front of the keyboards
+pub(crate) fn active_uid() -> Option<u32> {
+  let state = std::fs::read_to_string(Path::new(SEATS_DIR).join(SEAT)).ok()?;
+  state
+    .lines()
+    .find_map(|line| line.strip_prefix("ACTIVE_UID="))
+    .and_then(|uid| uid.parse().ok())
+}
+
+pub(crate) fn home_dir(uid: u32) -> Option<PathBuf> {
+  // nasskan is single threaded, so the static buffer of getpwuid is fine
+  let passwd = unsafe { libc::getpwuid(uid) };
+  if passwd.is_null() {
+    return None;
+  }
+
+  let home = unsafe { CStr::from_ptr((*passwd).pw_dir) };
+  Some(PathBuf::from(std::ffi::OsStr::from_bytes(home.to_bytes())))
+}
+
+// Reloads the config when another user becomes active, so their overlay is used
+pub(crate) struct SeatWorker {
+  inotify: RawFd,
+  active_uid: Option<u32>,
+}
+
+impl SeatWorker {
+  pub(crate) fn new() -> std::io::Result<Self> {
+    let inotify = unsafe { libc::inotify_init1(libc::IN_NONBLOCK | libc::IN_CLOEXEC) };
+    if inotify < 0 {
+      return Err(std::io::Error::last_os_error());
+    }
+
+    // logind replaces the file by renaming a new one
+    let path = CString::new(SEATS_DIR).unwrap();
+    let watch = unsafe {
+      libc::inotify_add_watch(
+        inotify,
+        path.as_ptr(),
+        libc::IN_MOVED_TO | libc::IN_CLOSE_WRITE,
+      )
+    };
+    if watch < 0 {
+      let error = std::io::Error::last_os_error();
+      unsafe { libc::close(inotify) };
+      return Err(error);
+    }
+
+    Ok(Self {
+      inotify,
+      active_uid: active_uid(),
+    })
+  }
+}
+
+impl AsRawFd for SeatWorker {
+  fn as_raw_fd(&self) -> RawFd {
+    self.inotify
+  }
+}
+
+impl AsyncWorker for SeatWorker {
+  fn step(&mut self, manager: &mut WorkerManager) {
+    // Which file changed doesn't matter. Just drain the events
+    let mut buffer = [0u8; 4096];
+    let pointer = buffer.as_mut_ptr() as *mut libc::c_void;
+    while unsafe { libc::read(self.inotify, pointer, buffer.len()) } > 0 {}
+
+    let active_uid = active_uid();
+    if active_uid == self.active_uid {
+      return;
+    }
+
+    info!("Active user changed. Reloading the config");
+    self.active_uid = active_uid;
+    if let Err(error) = manager.reload() {
+      error!("{}", error);
+    }
+  }
+
+  fn stop(&mut self) {
+    unsafe { libc::close(self.inotify) };
+  }
+}
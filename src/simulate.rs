@@ -0,0 +1,71 @@
+use crate::config::{Action, Config, EventKey};
+use crate::remapper::{Event, EventType, Remapper};
+use std::io::BufRead;
+
+// Remaps key events read from stdin, like "press A" or "release A", and explains each of them.
+// "profile NAME" switches profiles, and "profile" alone goes back to `then`.
+pub(crate) fn run(config: Result<Config, String>, device: usize) {
+  let config: &'static Config = Box::leak(Box::new(config.unwrap_or_else(|error| {
+    eprintln!("{}", error);
+    std::process::exit(1);
+  })));
+  let device = config.devices.get(device).unwrap_or_else(|| {
+    eprintln!("The config has only {} devices", config.devices.len());
+    std::process::exit(1);
+  });
+
+  let mut remapper = Remapper::new(device, false);
+  remapper.set_explaining(true);
+
+  for line in std::io::stdin().lock().lines() {
+    let line = line.unwrap();
+    let words: Vec<&str> = line.split_whitespace().collect();
+    match words.as_slice() {
+      [] => continue,
+      ["profile"] => {
+        remapper.switch_profile(None);
+      }
+      ["profile", profile] => {
+        remapper.switch_profile(Some(profile));
+      }
+      [event_type, key] => match parse_event(event_type, key) {
+        Ok(event) => {
+          remapper.remap(event);
+          match remapper.take_action() {
+            Some(Action::Profile(profile)) => {
+              println!("Switching to profile {:?}", profile);
+              remapper.switch_profile(profile.as_deref());
+            }
+            Some(Action::Pause) => println!("Pausing (not in simulation)"),
+            None => {}
+          }
+        }
+        Err(error) => {
+          eprintln!("{}", error);
+          continue;
+        }
+      },
+      _ => {
+        eprintln!("Expected \"press KEY\", \"release KEY\", \"repeat KEY\" or \"profile [NAME]\"");
+        continue;
+      }
+    }
+
+    for explanation in remapper.take_explanation() {
+      println!("{}", explanation);
+    }
+    println!();
+  }
+}
+
+fn parse_event(event_type: &str, key: &str) -> Result<Event, String> {
+  let event_type = match event_type {
+    "press" => EventType::Press,
+    "release" => EventType::Release,
+    "repeat" => EventType::Repeat,
+    _ => return Err(format!("{} is not press, release or repeat", event_type)),
+  };
+  let key: EventKey = key.parse()?;
+
+  Ok(Event { event_type, key })
+}
@@ -0,0 +1,168 @@
+use log::*;
+use std::fs::Permissions;
+use std::io::{Read, Write};
+use std::os::unix::fs::PermissionsExt;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::Path;
+use std::time::Duration;
+
+// Lines longer than this are not what nasskan expects. The connection gets closed
+const MAX_LINE_LENGTH: usize = 4096;
+// Same for more lines than this in a single read
+const MAX_LINES: usize = 64;
+const WRITE_TIMEOUT: Duration = Duration::from_secs(1);
+
+pub(crate) fn bind(path: &Path, mode: u32) -> std::io::Result<UnixListener> {
+  std::fs::create_dir_all(path.parent().unwrap())?;
+  // A socket file left by a previous instance makes bind fail
+  let _ = std::fs::remove_file(path);
+
+  let listener = UnixListener::bind(path)?;
+  listener.set_nonblocking(true)?;
+  std::fs::set_permissions(path, Permissions::from_mode(mode))?;
+  Ok(listener)
+}
+
+// Accepts every pending connection of a nonblocking listener
+pub(crate) fn accept_all(listener: &UnixListener) -> Vec<UnixStream> {
+  let mut result = Vec::new();
+  loop {
+    match listener.accept() {
+      Ok((stream, _)) => match stream.set_nonblocking(true) {
+        Ok(()) => result.push(stream),
+        Err(error) => error!("Accepting a connection failed: {:?}", error),
+      },
+      Err(ref error) if error.kind() == std::io::ErrorKind::WouldBlock => return result,
+      Err(error) => {
+        error!("Accepting a connection failed: {:?}", error);
+        return result;
+      }
+    }
+  }
+}
+
+// A nonblocking UnixStream speaking a line based protocol
+pub(crate) struct LineStream {
+  stream: UnixStream,
+  buffer: Vec<u8>,
+  is_closed: bool,
+}
+
+impl LineStream {
+  pub(crate) fn new(stream: UnixStream) -> Self {
+    Self {
+      stream,
+      buffer: Vec::new(),
+      is_closed: false,
+    }
+  }
+
+  pub(crate) fn is_closed(&self) -> bool {
+    self.is_closed
+  }
+
+  // Returns every complete line received so far, without line breaks
+  pub(crate) fn read_lines(&mut self) -> Vec<String> {
+    let mut lines = Vec::new();
+    let mut chunk = [0; 1024];
+    loop {
+      match self.stream.read(&mut chunk) {
+        Ok(0) => {
+          self.is_closed = true;
+          break;
+        }
+        Ok(size) => self.buffer.extend_from_slice(&chunk[..size]),
+        Err(ref error) if error.kind() == std::io::ErrorKind::WouldBlock => break,
+        Err(error) => {
+          debug!("Reading from a socket failed: {:?}", error);
+          self.is_closed = true;
+          break;
+        }
+      }
+
+      while let Some(end) = self.buffer.iter().position(|byte| *byte == b'\n') {
+        let line: Vec<u8> = self.buffer.drain(..=end).collect();
+        lines.push(String::from_utf8_lossy(&line).trim_end().to_string());
+      }
+      // Checked for each chunk, so a client writing without a break can't fill the memory
+      if MAX_LINE_LENGTH < self.buffer.len() || MAX_LINES < lines.len() {
+        warn!("Received too much at once. Closing the connection");
+        self.is_closed = true;
+        break;
+      }
+    }
+
+    lines
+  }
+
+  // Blocks until the whole line is written, because a long response doesn't fit in the socket
+  // buffer. The timeout is for clients which don't read
+  pub(crate) fn write_line(&mut self, line: &str) {
+    let mut message = line.as_bytes().to_vec();
+    message.push(b'\n');
+
+    let result = self
+      .stream
+      .set_nonblocking(false)
+      .and_then(|()| self.stream.set_write_timeout(Some(WRITE_TIMEOUT)))
+      .and_then(|()| self.stream.write_all(&message))
+      .and_then(|()| self.stream.set_nonblocking(true));
+    if let Err(error) = result {
+      debug!("Writing to a socket failed: {:?}", error);
+      self.is_closed = true;
+    }
+  }
+}
+
+impl AsRawFd for LineStream {
+  fn as_raw_fd(&self) -> RawFd {
+    self.stream.as_raw_fd()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::io::{BufRead, BufReader};
+
+  fn pair() -> (LineStream, UnixStream) {
+    let (ours, theirs) = UnixStream::pair().unwrap();
+    ours.set_nonblocking(true).unwrap();
+    (LineStream::new(ours), theirs)
+  }
+
+  #[test]
+  fn reads_complete_lines() {
+    let (mut stream, mut client) = pair();
+    client.write_all(b"one\ntwo\nthr").unwrap();
+    assert_eq!(stream.read_lines(), vec!["one".to_string(), "two".to_string()]);
+    client.write_all(b"ee\n").unwrap();
+    assert_eq!(stream.read_lines(), vec!["three".to_string()]);
+    assert!(!stream.is_closed());
+  }
+
+  #[test]
+  fn closes_when_a_line_is_too_long() {
+    let (mut stream, mut client) = pair();
+    client.set_nonblocking(true).unwrap();
+    // More than the socket buffer, which a single read loop must not keep
+    let _ = client.write_all(&vec![b'a'; 1 << 20]);
+    stream.read_lines();
+    assert!(stream.is_closed());
+  }
+
+  #[test]
+  fn writes_lines_longer_than_the_socket_buffer() {
+    let (mut stream, client) = pair();
+    let line = "a".repeat(1 << 20);
+    let reader = std::thread::spawn(move || {
+      let mut received = String::new();
+      BufReader::new(client).read_line(&mut received).unwrap();
+      received
+    });
+    stream.write_line(&line);
+    assert!(!stream.is_closed());
+    assert_eq!(reader.join().unwrap(), format!("{}\n", line));
+  }
+}
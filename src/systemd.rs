@@ -0,0 +1,82 @@
+use log::*;
+use nix::sys::socket::{self, AddressFamily, MsgFlags, SockAddr, SockFlag, SockType, UnixAddr};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::io::FromRawFd;
+use std::os::unix::net::UnixListener;
+use std::time::Duration;
+
+// https://www.freedesktop.org/software/systemd/man/sd_notify.html
+// Does nothing unless nasskan is started by systemd with Type=notify
+pub(crate) fn notify(state: &str) {
+  let path = match std::env::var_os("NOTIFY_SOCKET") {
+    Some(path) => path,
+    None => return,
+  };
+
+  if let Err(error) = send(path.as_bytes(), state) {
+    debug!("Notifying systemd failed: {:?}", error);
+  }
+}
+
+fn send(path: &[u8], state: &str) -> nix::Result<()> {
+  // Paths starting with @ are abstract sockets
+  let address = match path.split_first() {
+    Some((b'@', name)) => UnixAddr::new_abstract(name)?,
+    _ => UnixAddr::new(path)?,
+  };
+
+  let fd = socket::socket(
+    AddressFamily::Unix,
+    SockType::Datagram,
+    SockFlag::SOCK_CLOEXEC,
+    None,
+  )?;
+  let result = socket::sendto(
+    fd,
+    state.as_bytes(),
+    &SockAddr::Unix(address),
+    MsgFlags::empty(),
+  );
+  let _ = nix::unistd::close(fd);
+  result.map(|_| ())
+}
+
+// How often WATCHDOG=1 should be sent. Half of WatchdogSec, as systemd recommends
+pub(crate) fn watchdog_interval() -> Option<Duration> {
+  if !is_for_this_process("WATCHDOG_PID") {
+    return None;
+  }
+
+  let usec: u64 = std::env::var("WATCHDOG_USEC").ok()?.parse().ok()?;
+  Some(Duration::from_micros(usec / 2))
+}
+
+// https://www.freedesktop.org/software/systemd/man/sd_listen_fds.html
+// The control socket, if nasskan is started by nasskan.socket
+pub(crate) fn listened_socket() -> Option<UnixListener> {
+  if !is_for_this_process("LISTEN_PID") {
+    return None;
+  }
+  let count: i32 = std::env::var("LISTEN_FDS").ok()?.parse().ok()?;
+  std::env::remove_var("LISTEN_PID");
+  std::env::remove_var("LISTEN_FDS");
+  std::env::remove_var("LISTEN_FDNAMES");
+  if count != 1 {
+    warn!("Expected 1 socket from systemd, but got {}. Ignoring them", count);
+    return None;
+  }
+
+  // Passed file descriptors start from 3
+  let listener = unsafe { UnixListener::from_raw_fd(3) };
+  listener.set_nonblocking(true).ok()?;
+  Some(listener)
+}
+
+// The variable is absent or for another process if nasskan was started by something else
+fn is_for_this_process(name: &str) -> bool {
+  std::env::var(name)
+    .ok()
+    .and_then(|pid| pid.parse::<i32>().ok())
+    .map(|pid| pid == nix::unistd::getpid().as_raw())
+    .unwrap_or(false)
+}